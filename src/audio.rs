@@ -0,0 +1,343 @@
+use std::{f64::consts::PI, fmt, str::FromStr};
+
+use crate::error::Chip8Error;
+use crate::frontend::AudioSink;
+use crate::named;
+use crate::savestate::{StateReader, StateWriter};
+
+pub const PATTERN_SIZE: usize = 16;
+pub const PATTERN_BITS: usize = PATTERN_SIZE * 8;
+pub const DEFAULT_PITCH: u8 = 64;
+pub const SAMPLE_RATE: u32 = 44100;
+
+/// XO-CHIP sound state. While the sound timer runs, the 128 bit pattern is
+/// played one bit at a time, looping, at a rate picked by the pitch register.
+pub struct Audio {
+    // None until a program loads one with F002
+    pattern: Option<[u8; PATTERN_SIZE]>,
+    pitch: u8,
+}
+
+impl Audio {
+    pub fn init() -> Audio {
+        Audio {
+            pattern: None,
+            pitch: DEFAULT_PITCH,
+        }
+    }
+
+    pub fn set_pattern(&mut self, pattern: [u8; PATTERN_SIZE]) {
+        self.pattern = Some(pattern);
+    }
+
+    pub fn get_pattern(&self) -> Option<&[u8; PATTERN_SIZE]> {
+        self.pattern.as_ref()
+    }
+
+    pub fn set_pitch(&mut self, pitch: u8) {
+        self.pitch = pitch;
+    }
+
+    pub fn get_pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    /// Pattern bits played per second, 4000 at the default pitch
+    pub fn playback_rate(&self) -> f64 {
+        4000.0 * 2f64.powf((f64::from(self.pitch) - 64.0) / 48.0)
+    }
+
+    pub fn save_state(&self, out: &mut StateWriter) {
+        out.put_bool(self.pattern.is_some());
+        out.put_bytes(&self.pattern.unwrap_or_default());
+        out.put_u8(self.pitch);
+    }
+
+    pub fn load_state(&mut self, input: &mut StateReader) -> Result<(), Chip8Error> {
+        let loaded = input.get_bool()?;
+        let mut pattern = [0; PATTERN_SIZE];
+        pattern.copy_from_slice(input.get_bytes(PATTERN_SIZE)?);
+
+        self.pattern = if loaded { Some(pattern) } else { None };
+        self.pitch = input.get_u8()?;
+        Ok(())
+    }
+}
+
+/// Shape of the buzzer tone
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Waveform {
+    Square,
+    Triangle,
+    Sawtooth,
+    Sine,
+}
+
+impl Waveform {
+    pub const ALL: [Waveform; 4] = [
+        Waveform::Square,
+        Waveform::Triangle,
+        Waveform::Sawtooth,
+        Waveform::Sine,
+    ];
+
+    /// Value between -1.0 and 1.0 at `phase`, which goes from 0.0 to 1.0 over a period
+    pub fn sample(self, phase: f64) -> f32 {
+        let value = match self {
+            Waveform::Square => {
+                if phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Waveform::Sawtooth => 2.0 * phase - 1.0,
+            Waveform::Sine => (2.0 * PI * phase).sin(),
+        };
+
+        value as f32
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Waveform::Square => "square",
+            Waveform::Triangle => "triangle",
+            Waveform::Sawtooth => "sawtooth",
+            Waveform::Sine => "sine",
+        }
+    }
+}
+
+impl fmt::Display for Waveform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Waveform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        named::parse_named(&Waveform::ALL, Waveform::name, "waveform", s)
+    }
+}
+
+/// Tone played while the sound timer runs and no XO-CHIP pattern is loaded
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Buzzer {
+    pub waveform: Waveform,
+    /// Hz
+    pub frequency: f32,
+    /// From 0.0 (silent) to 1.0, also applies to XO-CHIP patterns
+    pub volume: f32,
+}
+
+impl Default for Buzzer {
+    fn default() -> Self {
+        Buzzer {
+            waveform: Waveform::Square,
+            frequency: 440.0,
+            volume: 0.25,
+        }
+    }
+}
+
+/// Turns the audio state into PCM samples at a fixed sample rate
+pub struct SampleGenerator {
+    sample_rate: u32,
+    buzzer: Buzzer,
+    // position in the pattern, in bits
+    position: f64,
+    // position in the buzzer period, from 0.0 to 1.0
+    phase: f64,
+}
+
+impl SampleGenerator {
+    pub fn init(sample_rate: u32) -> SampleGenerator {
+        SampleGenerator::with_buzzer(sample_rate, Buzzer::default())
+    }
+
+    pub fn with_buzzer(sample_rate: u32, buzzer: Buzzer) -> SampleGenerator {
+        SampleGenerator {
+            sample_rate,
+            buzzer,
+            position: 0.0,
+            phase: 0.0,
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn buzzer(&self) -> &Buzzer {
+        &self.buzzer
+    }
+
+    /// Samples in one 60 Hz frame
+    pub fn samples_per_frame(&self) -> usize {
+        self.sample_rate as usize / 60
+    }
+
+    /// Fills `out` with samples between -1.0 and 1.0. Plays the XO-CHIP
+    /// pattern if one was loaded and the buzzer otherwise, or writes silence
+    /// while `playing` is false.
+    pub fn generate(&mut self, audio: &Audio, playing: bool, out: &mut [f32]) {
+        if !playing {
+            out.fill(0.0);
+            return;
+        }
+
+        let volume = self.buzzer.volume;
+
+        match audio.get_pattern() {
+            Some(pattern) => {
+                let step = audio.playback_rate() / f64::from(self.sample_rate);
+
+                for sample in out.iter_mut() {
+                    let bit = self.position as usize;
+                    let lit = pattern[bit / 8] & (0x80 >> (bit % 8)) != 0;
+
+                    *sample = if lit { volume } else { -volume };
+
+                    self.position = (self.position + step) % PATTERN_BITS as f64;
+                }
+            }
+
+            None => {
+                let step = f64::from(self.buzzer.frequency) / f64::from(self.sample_rate);
+
+                for sample in out.iter_mut() {
+                    *sample = self.buzzer.waveform.sample(self.phase) * volume;
+
+                    self.phase = (self.phase + step).fract();
+                }
+            }
+        }
+    }
+
+    /// Generates the samples of one 60 Hz frame
+    pub fn generate_frame(&mut self, audio: &Audio, playing: bool) -> Vec<f32> {
+        let mut samples = vec![0.0; self.samples_per_frame()];
+        self.generate(audio, playing, &mut samples);
+        samples
+    }
+}
+
+/// Audio driver that generates samples and throws them away. Keeps the last
+/// frame around so headless runs and tests can look at it.
+pub struct NullAudio {
+    generator: SampleGenerator,
+    last_frame: Vec<f32>,
+}
+
+impl NullAudio {
+    pub fn init() -> NullAudio {
+        NullAudio::with_generator(SampleGenerator::init(SAMPLE_RATE))
+    }
+
+    pub fn with_generator(generator: SampleGenerator) -> NullAudio {
+        NullAudio {
+            generator,
+            last_frame: Vec::new(),
+        }
+    }
+
+    pub fn last_frame(&self) -> &[f32] {
+        &self.last_frame
+    }
+}
+
+impl AudioSink for NullAudio {
+    fn play_frame(&mut self, audio: &Audio, playing: bool) {
+        self.last_frame = self.generator.generate_frame(audio, playing);
+    }
+}
+
+#[cfg(test)]
+mod audio_tests {
+    use super::*;
+
+    fn loud() -> Buzzer {
+        Buzzer {
+            volume: 1.0,
+            ..Buzzer::default()
+        }
+    }
+
+    #[test]
+    fn test_silent_while_not_playing() {
+        let audio = Audio::init();
+        let mut generator = SampleGenerator::init(8000);
+        let mut out = [1.0; 16];
+
+        generator.generate(&audio, false, &mut out);
+        assert!(out.iter().all(|&sample| sample == 0.0));
+    }
+
+    #[test]
+    fn test_buzzer_waveforms() {
+        let audio = Audio::init();
+        let buzzer = Buzzer {
+            frequency: 1000.0,
+            ..loud()
+        };
+
+        let mut square = SampleGenerator::with_buzzer(4000, buzzer);
+        let mut out = [0.0; 4];
+        square.generate(&audio, true, &mut out);
+        assert_eq!(out, [1.0, 1.0, -1.0, -1.0]);
+
+        let sawtooth = Buzzer {
+            waveform: Waveform::Sawtooth,
+            ..buzzer
+        };
+        let mut generator = SampleGenerator::with_buzzer(4000, sawtooth);
+        generator.generate(&audio, true, &mut out);
+        assert_eq!(out, [-1.0, -0.5, 0.0, 0.5]);
+    }
+
+    #[test]
+    fn test_volume() {
+        let audio = Audio::init();
+        let mut generator = SampleGenerator::init(8000);
+        let mut out = [0.0; 8];
+
+        generator.generate(&audio, true, &mut out);
+        assert!(out.iter().all(|sample| sample.abs() == 0.25));
+    }
+
+    #[test]
+    fn test_pattern_playback() {
+        let mut audio = Audio::init();
+        let mut pattern = [0; PATTERN_SIZE];
+        pattern[0] = 0b1010_0000;
+        audio.set_pattern(pattern);
+
+        // one sample per bit at the default pitch
+        let mut generator = SampleGenerator::with_buzzer(4000, loud());
+        let mut out = [0.0; 4];
+        generator.generate(&audio, true, &mut out);
+        assert_eq!(out, [1.0, -1.0, 1.0, -1.0]);
+    }
+
+    #[test]
+    fn test_pitch() {
+        let mut audio = Audio::init();
+        assert_eq!(audio.playback_rate(), 4000.0);
+
+        audio.set_pitch(DEFAULT_PITCH + 48);
+        assert_eq!(audio.playback_rate(), 8000.0);
+    }
+
+    #[test]
+    fn test_null_audio_frame() {
+        let audio = Audio::init();
+        let mut sink = NullAudio::init();
+
+        sink.play_frame(&audio, true);
+        assert_eq!(sink.last_frame().len(), 735);
+        assert!(sink.last_frame().iter().any(|&sample| sample != 0.0));
+    }
+}
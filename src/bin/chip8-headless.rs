@@ -0,0 +1,374 @@
+//! Runs a ROM without a window and prints the display hash, e.g.
+//! `chip8-headless rom.ch8 --frames 600`. Never opens SDL, so it works on
+//! machines with no display or sound card.
+//!
+//! `chip8-headless trace-diff a.trace b.trace` compares two `--trace` logs
+//! and shows the first instruction where they differ.
+
+use std::{env, process};
+
+use chip_8::{
+    audio::{self, SampleGenerator},
+    capture::{CaptureFormat, FrameCapture},
+    chip8::{Chip8, EmulatorState},
+    debugger::Debugger,
+    error::Chip8Error,
+    frontend::AudioSink,
+    gdb::GdbStub,
+    movie::{Movie, Player},
+    options::MachineOptions,
+    screenshot::Screenshot,
+    trace::{self, TraceOptions},
+    wav::WavWriter,
+};
+
+const USAGE: &str = "usage: chip8-headless <rom> [--frames <n>] [--at <frame,frame,...>] [--text]
+             [--platform vip|chip48|schip|xochip] [--ips <n>]
+             [--timing flat|vip] [--seed <n>] [--random splitmix|page-sum]
+             [--state <file>] [--play <movie> | --debug | --gdb <port>]
+             [--wav <file>] [--waveform square|triangle|sawtooth|sine]
+             [--frequency <hz>] [--volume <0-1>]
+             [--screenshot <file.png|file.pbm|file.txt>] [--gif <file> | --png-frames <dir>]
+             [--scale <n>]
+             [--trace <file> [--trace-pc <start-end>] [--trace-op <0-F,...>]
+                             [--trace-frames <start-end>]]
+       chip8-headless trace-diff <trace> <trace> [--context <n>]";
+
+const DEFAULT_CONTEXT: usize = 3;
+
+const DEFAULT_FRAMES: u32 = 600;
+
+struct Options {
+    rom: String,
+    frames: u32,
+    // frames after which the display is reported, the last frame always is
+    report_at: Vec<u32>,
+    text: bool,
+    // --seed defaults to 0 here so runs repeat
+    machine: MachineOptions,
+    play: Option<String>,
+    // start paused in the stdin debugger
+    debug: bool,
+    // wait for a GDB client on this local port
+    gdb: Option<u16>,
+    wav: Option<String>,
+    // written after the last frame
+    screenshot: Option<String>,
+    // every frame goes here
+    capture: Option<(CaptureFormat, String)>,
+    scale: usize,
+    trace: TraceOptions,
+}
+
+fn main() {
+    let mut args = env::args().skip(1).peekable();
+    if args.peek().map(String::as_str) == Some("trace-diff") {
+        args.next();
+        trace_diff(args);
+    }
+
+    let options = match parse_args(args) {
+        Ok(options) => options,
+        Err(err) => usage_error(&err),
+    };
+
+    if let Err(err) = run(&options) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
+
+fn run(options: &Options) -> Result<(), Chip8Error> {
+    let mut config = options.machine.config();
+    config.seed = Some(options.machine.seed.unwrap_or(0));
+
+    let mut emulator = Chip8::with_config(config);
+    emulator.load_rom_file(&options.rom)?;
+
+    if let Some(path) = &options.machine.state {
+        emulator.load_state_file(path)?;
+    }
+
+    if let Some(tracer) = options.trace.create_tracer()? {
+        emulator.set_tracer(tracer);
+    }
+
+    let mut player = match &options.play {
+        Some(path) => {
+            let mut player = Player::init(Movie::load(path)?);
+            player.start(&mut emulator)?;
+            Some(player)
+        }
+        None => None,
+    };
+
+    let mut wav_writer = options.wav.as_ref().map(|_| {
+        let generator = SampleGenerator::with_buzzer(audio::SAMPLE_RATE, options.machine.buzzer);
+        WavWriter::with_generator(generator)
+    });
+
+    let mut capture = match &options.capture {
+        Some((format, path)) => Some(FrameCapture::start(
+            *format,
+            path,
+            emulator.bus.get_display(),
+            options.scale,
+        )?),
+        None => None,
+    };
+
+    let mut debugger = options.debug.then(Debugger::with_stdin);
+    if let Some(debugger) = &mut debugger {
+        debugger.pause(&mut emulator);
+    }
+    let mut gdb = options.gdb.map(GdbStub::listen).transpose()?;
+
+    let mut frame = 0;
+    while frame < options.frames {
+        if let Some(player) = &mut player {
+            player.apply_inputs(&mut emulator);
+        }
+
+        // only frames the debugger or gdb finished count
+        if let Some(debugger) = &mut debugger {
+            debugger.poll_commands(&mut emulator);
+            if debugger.is_paused() {
+                debugger.wait_command(&mut emulator);
+            }
+
+            if emulator.state == EmulatorState::Quit {
+                break;
+            }
+            if !debugger.run_frame(&mut emulator)? {
+                continue;
+            }
+        } else if let Some(gdb) = &mut gdb {
+            gdb.poll(&mut emulator);
+            if gdb.is_halted() {
+                gdb.wait(&mut emulator);
+            }
+
+            if emulator.state == EmulatorState::Quit {
+                break;
+            }
+            if !gdb.run_frame(&mut emulator)? {
+                continue;
+            }
+        } else {
+            emulator.run_frame()?;
+        }
+        frame += 1;
+
+        if let Some(writer) = &mut wav_writer {
+            writer.play_frame(emulator.bus.get_audio(), emulator.is_sound_playing());
+        }
+
+        if let Some(capture) = &mut capture {
+            capture.capture(emulator.bus.get_display())?;
+        }
+
+        if let Some(player) = &mut player {
+            if let Some(desync) = player.end_frame(&emulator) {
+                eprintln!("{}", desync);
+                process::exit(2);
+            }
+        }
+
+        // 00FD ends the run early, its last frame is still reported
+        let quit = emulator.state == EmulatorState::Quit;
+        if quit || frame == options.frames || options.report_at.contains(&frame) {
+            report(&emulator, frame, options.text);
+        }
+        if quit {
+            break;
+        }
+    }
+
+    if let (Some(writer), Some(path)) = (wav_writer, &options.wav) {
+        writer.save(path).map_err(Chip8Error::Output)?;
+    }
+
+    if let Some(capture) = capture {
+        capture.finish()?;
+    }
+
+    if let Some(path) = &options.screenshot {
+        Screenshot::with_scale(options.scale).save(emulator.bus.get_display(), path)?;
+    }
+
+    if let Some(tracer) = emulator.take_tracer() {
+        tracer.finish()?;
+    }
+
+    Ok(())
+}
+
+// one `frame <n> hash <hex>` line, followed by the screen when asked for
+fn report(emulator: &Chip8, frame: u32, text: bool) {
+    println!("frame {} hash {:016x}", frame, emulator.bus.display_hash());
+
+    if text {
+        print!("{}", emulator.bus.display_to_text());
+    }
+}
+
+/// Exits with 0 when the traces agree and 2 when they differ, like a movie
+/// desync
+fn trace_diff(mut args: impl Iterator<Item = String>) -> ! {
+    let mut files = Vec::new();
+    let mut context = DEFAULT_CONTEXT;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--context" => {
+                let value = args.next().unwrap_or_default();
+                context = match value.parse() {
+                    Ok(context) => context,
+                    Err(_) => usage_error(&format!("invalid context {}", value)),
+                };
+            }
+            _ if arg.starts_with("--") => usage_error(&format!("unknown option {}", arg)),
+            _ => files.push(arg),
+        }
+    }
+
+    let [a_name, b_name] = &files[..] else {
+        usage_error("trace-diff needs two trace files");
+    };
+
+    let traces = trace::read_trace(a_name).and_then(|a| Ok((a, trace::read_trace(b_name)?)));
+    let (a, b) = match traces {
+        Ok(traces) => traces,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
+
+    match trace::diff(&a, &b) {
+        Some(divergence) => {
+            print!("{}", divergence.report(a_name, &a, b_name, &b, context));
+            process::exit(2);
+        }
+        None => {
+            println!("traces match over {} instructions", a.len());
+            process::exit(0);
+        }
+    }
+}
+
+fn usage_error(err: &str) -> ! {
+    eprintln!("{}\n{}", err, USAGE);
+    process::exit(1);
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut rom = None;
+    let mut frames = DEFAULT_FRAMES;
+    let mut report_at = Vec::new();
+    let mut text = false;
+    let mut machine = MachineOptions::init();
+    let mut play = None;
+    let mut debug = false;
+    let mut gdb = None;
+    let mut wav = None;
+    let mut screenshot = None;
+    let mut capture = None;
+    let mut scale = 1;
+    let mut trace = TraceOptions::init();
+
+    while let Some(arg) = args.next() {
+        if machine.parse_arg(&arg, &mut args)? || trace.parse_arg(&arg, &mut args)? {
+            continue;
+        }
+
+        match arg.as_str() {
+            "--frames" => {
+                let value = args.next().ok_or("--frames needs a value")?;
+                frames = value
+                    .parse()
+                    .map_err(|_| format!("invalid frame count {}", value))?;
+            }
+            "--at" => {
+                let value = args.next().ok_or("--at needs a list of frames")?;
+                for frame in value.split(',') {
+                    report_at.push(
+                        frame
+                            .trim()
+                            .parse()
+                            .map_err(|_| format!("invalid frame {}", frame))?,
+                    );
+                }
+            }
+            "--text" => text = true,
+            "--play" => {
+                play = Some(args.next().ok_or("--play needs a file name")?);
+            }
+            "--debug" => debug = true,
+            "--gdb" => {
+                let value = args.next().ok_or("--gdb needs a port")?;
+                gdb = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("invalid port {}", value))?,
+                );
+            }
+            "--wav" => {
+                wav = Some(args.next().ok_or("--wav needs a file name")?);
+            }
+            "--screenshot" => {
+                screenshot = Some(args.next().ok_or("--screenshot needs a file name")?);
+            }
+            "--gif" | "--png-frames" => {
+                if capture.is_some() {
+                    return Err("--gif and --png-frames cannot be combined".to_string());
+                }
+
+                let path = args
+                    .next()
+                    .ok_or_else(|| format!("{} needs a file name", arg))?;
+                let format = if arg == "--gif" {
+                    CaptureFormat::Gif
+                } else {
+                    CaptureFormat::PngSequence
+                };
+                capture = Some((format, path));
+            }
+            "--scale" => {
+                let value = args.next().ok_or("--scale needs a value")?;
+                scale = match value.parse() {
+                    Ok(scale) if scale > 0 => scale,
+                    _ => return Err(format!("invalid scale {}", value)),
+                };
+            }
+            _ if rom.is_none() && !arg.starts_with("--") => rom = Some(arg),
+            _ => return Err(format!("unexpected argument {}", arg)),
+        }
+    }
+
+    if (debug || gdb.is_some()) && play.is_some() {
+        return Err("--debug and --gdb cannot be used with a movie".to_string());
+    }
+
+    if debug && gdb.is_some() {
+        return Err("--debug and --gdb cannot be used together".to_string());
+    }
+
+    trace.check()?;
+
+    Ok(Options {
+        rom: rom.ok_or("CHIP8 ROM path is required")?,
+        frames,
+        report_at,
+        text,
+        machine,
+        play,
+        debug,
+        gdb,
+        wav,
+        screenshot,
+        capture,
+        scale,
+        trace,
+    })
+}
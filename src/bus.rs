@@ -1,30 +1,134 @@
+use crate::audio;
 use crate::display;
+use crate::error::Chip8Error;
+use crate::platform::PlatformConfig;
 use crate::ram;
+use crate::savestate::{StateReader, StateWriter};
+use crate::watch::{WatchHit, Watchpoint};
 
 pub struct Bus {
     ram: ram::RAM,
     display: display::Display,
     keypad: [bool; 16],
+    audio: audio::Audio,
+    // debugger hooks on the reads and writes of instructions
+    watchpoints: Vec<Watchpoint>,
+    watch_hit: Option<WatchHit>,
 }
 
 impl Bus {
     pub fn init() -> Bus {
+        Bus::for_platform(&PlatformConfig::default())
+    }
+
+    pub fn for_platform(config: &PlatformConfig) -> Bus {
         Bus {
-            ram: ram::RAM::init(),
-            display: display::Display::init(),
+            ram: ram::RAM::with_size(config.memory_size),
+            display: display::Display::with_size(config.display_width, config.display_height),
             keypad: [false; 16],
+            audio: audio::Audio::init(),
+            watchpoints: Vec::new(),
+            watch_hit: None,
         }
     }
 
-    // Memory
-    pub fn ram_write_byte(&mut self, addr: u16, value: u8) {
+    // Memory, as accessed by instructions
+    pub fn ram_write_byte(&mut self, addr: u16, value: u8) -> Result<(), Chip8Error> {
+        if !self.watchpoints.is_empty() {
+            let old = self.ram.read_byte(addr)?;
+            let hit = self
+                .watchpoints
+                .iter()
+                .find(|watchpoint| watchpoint.on_write(addr, old, value));
+            self.record_watch_hit(hit.copied(), addr, true, old, value);
+        }
+
         self.ram.write_byte(addr, value)
     }
 
-    pub fn ram_read_byte(&mut self, addr: u16) -> u8 {
+    pub fn ram_read_byte(&mut self, addr: u16) -> Result<u8, Chip8Error> {
+        let value = self.ram.read_byte(addr)?;
+
+        if !self.watchpoints.is_empty() {
+            let hit = self
+                .watchpoints
+                .iter()
+                .find(|watchpoint| watchpoint.on_read(addr));
+            self.record_watch_hit(hit.copied(), addr, false, value, value);
+        }
+        Ok(value)
+    }
+
+    /// Reads memory without triggering watchpoints, for fetching opcodes
+    /// and for debuggers
+    pub fn ram_peek(&self, addr: u16) -> Result<u8, Chip8Error> {
         self.ram.read_byte(addr)
     }
 
+    /// Writes memory without triggering watchpoints, for loading ROMs and
+    /// for debuggers
+    pub fn ram_poke(&mut self, addr: u16, value: u8) -> Result<(), Chip8Error> {
+        self.ram.write_byte(addr, value)
+    }
+
+    pub fn ram_size(&self) -> usize {
+        self.ram.size()
+    }
+
+    // Watchpoints
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        if !self.watchpoints.contains(&watchpoint) {
+            self.watchpoints.push(watchpoint);
+        }
+    }
+
+    pub fn remove_watchpoint(&mut self, watchpoint: &Watchpoint) -> bool {
+        let len = self.watchpoints.len();
+        self.watchpoints.retain(|w| w != watchpoint);
+        self.watchpoints.len() < len
+    }
+
+    /// Removes every watchpoint starting at `addr` and returns how many
+    pub fn remove_watchpoints_at(&mut self, addr: u16) -> usize {
+        let len = self.watchpoints.len();
+        self.watchpoints.retain(|w| w.addr != addr);
+        len - self.watchpoints.len()
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+        self.watch_hit = None;
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// First access that hit a watchpoint since the last call
+    pub fn take_watch_hit(&mut self) -> Option<WatchHit> {
+        self.watch_hit.take()
+    }
+
+    // keeps the first hit until it is taken
+    fn record_watch_hit(
+        &mut self,
+        watchpoint: Option<Watchpoint>,
+        addr: u16,
+        write: bool,
+        old: u8,
+        value: u8,
+    ) {
+        if let (Some(watchpoint), None) = (watchpoint, self.watch_hit) {
+            self.watch_hit = Some(WatchHit {
+                watchpoint,
+                addr,
+                write,
+                old,
+                value,
+            });
+        }
+    }
+
     // Display
     pub fn display_clear(&mut self) {
         self.display.clear();
@@ -41,6 +145,67 @@ impl Bus {
     pub fn display_get_buffer(&self) -> &[u32] {
         self.display.get_buffer()
     }
+
+    pub fn display_toggle_pixel(&mut self, x: usize, y: usize, plane: u32) -> bool {
+        self.display.toggle_pixel(x, y, plane)
+    }
+
+    pub fn display_select_planes(&mut self, planes: u32) {
+        self.display.select_planes(planes);
+    }
+
+    pub fn display_selected_planes(&self) -> u32 {
+        self.display.selected_planes()
+    }
+
+    pub fn display_set_hires(&mut self, hires: bool) {
+        self.display.set_hires(hires);
+    }
+
+    pub fn display_scroll_down(&mut self, rows: usize) {
+        self.display.scroll_down(rows);
+    }
+
+    pub fn display_scroll_up(&mut self, rows: usize) {
+        self.display.scroll_up(rows);
+    }
+
+    pub fn display_scroll_right(&mut self, cols: usize) {
+        self.display.scroll_right(cols);
+    }
+
+    pub fn display_scroll_left(&mut self, cols: usize) {
+        self.display.scroll_left(cols);
+    }
+
+    pub fn display_hash(&self) -> u64 {
+        self.display.hash()
+    }
+
+    pub fn display_to_text(&self) -> String {
+        self.display.to_text()
+    }
+
+    pub fn get_display(&self) -> &display::Display {
+        &self.display
+    }
+
+    pub fn display_width(&self) -> usize {
+        self.display.width()
+    }
+
+    pub fn display_height(&self) -> usize {
+        self.display.height()
+    }
+
+    pub fn display_logical_width(&self) -> usize {
+        self.display.logical_width()
+    }
+
+    pub fn display_logical_height(&self) -> usize {
+        self.display.logical_height()
+    }
+
     // Keyboard
     pub fn is_key_pressed(&self, index: usize) -> bool {
         self.keypad[index]
@@ -53,6 +218,40 @@ impl Bus {
     pub fn get_keypad(&self) -> &[bool; 16] {
         &self.keypad
     }
+
+    // Audio
+    pub fn audio_set_pattern(&mut self, pattern: [u8; audio::PATTERN_SIZE]) {
+        self.audio.set_pattern(pattern);
+    }
+
+    pub fn audio_set_pitch(&mut self, pitch: u8) {
+        self.audio.set_pitch(pitch);
+    }
+
+    pub fn get_audio(&self) -> &audio::Audio {
+        &self.audio
+    }
+}
+
+/// Save states
+impl Bus {
+    pub fn save_state(&self, out: &mut StateWriter) {
+        self.ram.save_state(out);
+        self.display.save_state(out);
+        for &key in &self.keypad {
+            out.put_bool(key);
+        }
+        self.audio.save_state(out);
+    }
+
+    pub fn load_state(&mut self, input: &mut StateReader) -> Result<(), Chip8Error> {
+        self.ram.load_state(input)?;
+        self.display.load_state(input)?;
+        for key in self.keypad.iter_mut() {
+            *key = input.get_bool()?;
+        }
+        self.audio.load_state(input)
+    }
 }
 
 /// For printing values
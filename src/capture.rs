@@ -0,0 +1,349 @@
+use std::{
+    borrow::Cow,
+    fmt,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::display::Display;
+use crate::error::Chip8Error;
+use crate::named;
+use crate::screenshot::Screenshot;
+
+// GIF delays are in 1/100 s, frames come at 60 Hz
+const CENTISECONDS_PER_SECOND: u64 = 100;
+const FRAMES_PER_SECOND: u64 = 60;
+
+// most viewers play shorter delays as 1/10 s
+const MIN_GIF_DELAY: u64 = 2;
+
+/// What a `FrameCapture` writes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureFormat {
+    Gif,
+    /// One PNG file per frame in a directory
+    PngSequence,
+}
+
+impl CaptureFormat {
+    pub const ALL: [CaptureFormat; 2] = [CaptureFormat::Gif, CaptureFormat::PngSequence];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CaptureFormat::Gif => "gif",
+            CaptureFormat::PngSequence => "png",
+        }
+    }
+}
+
+impl fmt::Display for CaptureFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for CaptureFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        named::parse_named(
+            &CaptureFormat::ALL,
+            CaptureFormat::name,
+            "capture format",
+            s,
+        )
+    }
+}
+
+/// Animated GIF of the display, one 60 Hz frame at a time. The GIF has two
+/// colours, the first two of the palette, so every lit pixel gets the
+/// foreground colour whatever its XO-CHIP plane. Repeated frames only
+/// lengthen the previous one, and a frame shown for less than 2/100 s is
+/// dropped, keeping the timing of the others. At 60 Hz that drops every
+/// state that lasts a single frame, which is most CHIP-8 sprite flicker,
+/// so use `CaptureFormat::PngSequence` to keep every presented frame.
+pub struct GifWriter<W: Write> {
+    encoder: gif::Encoder<W>,
+    screenshot: Screenshot,
+    width: u16,
+    height: u16,
+    // frame waiting for the next different one to know its delay
+    pending: Option<Vec<u8>>,
+    // frame with a known delay, held back so a later identical one can
+    // lengthen it after a short frame between them was dropped
+    ready: Option<(Vec<u8>, u64)>,
+    frames: u64,
+    // time the pending frame starts at
+    written: u64,
+}
+
+impl<W: Write> GifWriter<W> {
+    pub fn init(out: W, display: &Display, scale: usize) -> io::Result<GifWriter<W>> {
+        let screenshot = Screenshot::with_scale(scale);
+        let (width, height) = screenshot.size(display);
+        let (width, height) = (gif_size(width)?, gif_size(height)?);
+
+        let mut palette = Vec::with_capacity(6);
+        for color in &screenshot.palette[..2] {
+            palette.extend_from_slice(&color.to_be_bytes()[..3]);
+        }
+
+        let mut encoder = gif::Encoder::new(out, width, height, &palette).map_err(gif_error)?;
+        encoder
+            .set_repeat(gif::Repeat::Infinite)
+            .map_err(gif_error)?;
+
+        Ok(GifWriter {
+            encoder,
+            screenshot,
+            width,
+            height,
+            pending: None,
+            ready: None,
+            frames: 0,
+            written: 0,
+        })
+    }
+
+    /// Frames added so far
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn add_frame(&mut self, display: &Display) -> io::Result<()> {
+        let start = centiseconds(self.frames);
+        self.frames += 1;
+
+        let mut pixels = Vec::with_capacity(usize::from(self.width) * usize::from(self.height));
+        for row in self.screenshot.scaled_rows(display) {
+            pixels.extend(row.map(|pixel| u8::from(pixel != 0)));
+        }
+
+        match self.pending.take() {
+            Some(pending) if pending == pixels => {
+                self.pending = Some(pending);
+                return Ok(());
+            }
+            Some(pending) if start - self.written >= MIN_GIF_DELAY => {
+                self.push_frame(pending, start - self.written)?;
+                self.written = start;
+            }
+            // too short to show, the new frame takes its place
+            _ => {}
+        }
+
+        self.pending = Some(pixels);
+        Ok(())
+    }
+
+    /// Writes the last frame and hands back the output
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(pending) = self.pending.take() {
+            let delay = (centiseconds(self.frames) - self.written).max(MIN_GIF_DELAY);
+            self.push_frame(pending, delay)?;
+        }
+
+        if let Some((pixels, delay)) = self.ready.take() {
+            self.write_frame(pixels, delay)?;
+        }
+
+        self.encoder.into_inner()
+    }
+
+    // writes the frame before unless `pixels` repeats it
+    fn push_frame(&mut self, pixels: Vec<u8>, delay: u64) -> io::Result<()> {
+        if let Some((ready, ready_delay)) = &mut self.ready {
+            if *ready == pixels {
+                *ready_delay += delay;
+                return Ok(());
+            }
+        }
+
+        match self.ready.replace((pixels, delay)) {
+            Some((ready, ready_delay)) => self.write_frame(ready, ready_delay),
+            None => Ok(()),
+        }
+    }
+
+    fn write_frame(&mut self, pixels: Vec<u8>, delay: u64) -> io::Result<()> {
+        let frame = gif::Frame {
+            width: self.width,
+            height: self.height,
+            delay: delay.min(u64::from(u16::MAX)) as u16,
+            buffer: Cow::Owned(pixels),
+            ..gif::Frame::default()
+        };
+
+        self.encoder.write_frame(&frame).map_err(gif_error)
+    }
+}
+
+// time since the start of the capture after `frames` frames, in 1/100 s
+fn centiseconds(frames: u64) -> u64 {
+    frames * CENTISECONDS_PER_SECOND / FRAMES_PER_SECOND
+}
+
+fn gif_size(size: usize) -> io::Result<u16> {
+    u16::try_from(size).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} pixels is too big for a GIF", size),
+        )
+    })
+}
+
+fn gif_error(err: gif::EncodingError) -> io::Error {
+    match err {
+        gif::EncodingError::Io(err) => err,
+        err => io::Error::other(err),
+    }
+}
+
+enum Target {
+    Gif(GifWriter<BufWriter<File>>),
+    PngSequence { screenshot: Screenshot, frames: u64 },
+}
+
+/// Recording of every frame to a file, see `CaptureFormat`
+pub struct FrameCapture {
+    path: PathBuf,
+    target: Target,
+}
+
+impl FrameCapture {
+    /// Starts a GIF at `path` or a PNG sequence in the directory `path`,
+    /// which is created if needed. Every screen pixel becomes a `scale` by
+    /// `scale` block
+    pub fn start<P: AsRef<Path>>(
+        format: CaptureFormat,
+        path: P,
+        display: &Display,
+        scale: usize,
+    ) -> Result<FrameCapture, Chip8Error> {
+        let path = path.as_ref().to_path_buf();
+
+        let target = match format {
+            CaptureFormat::Gif => {
+                let file = File::create(&path).map_err(Chip8Error::Output)?;
+                let writer = GifWriter::init(BufWriter::new(file), display, scale)
+                    .map_err(Chip8Error::Output)?;
+                Target::Gif(writer)
+            }
+
+            CaptureFormat::PngSequence => {
+                fs::create_dir_all(&path).map_err(Chip8Error::Output)?;
+                Target::PngSequence {
+                    screenshot: Screenshot::with_scale(scale),
+                    frames: 0,
+                }
+            }
+        };
+
+        Ok(FrameCapture { path, target })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn frames(&self) -> u64 {
+        match &self.target {
+            Target::Gif(writer) => writer.frames(),
+            Target::PngSequence { frames, .. } => *frames,
+        }
+    }
+
+    /// Adds the display as the next 60 Hz frame
+    pub fn capture(&mut self, display: &Display) -> Result<(), Chip8Error> {
+        match &mut self.target {
+            Target::Gif(writer) => writer.add_frame(display).map_err(Chip8Error::Output),
+
+            // frame000001.png, frame000002.png, ...
+            Target::PngSequence { screenshot, frames } => {
+                *frames += 1;
+                let path = self.path.join(format!("frame{:06}.png", frames));
+                screenshot.save(display, path)
+            }
+        }
+    }
+
+    pub fn finish(self) -> Result<(), Chip8Error> {
+        match self.target {
+            Target::Gif(writer) => writer
+                .finish()
+                .and_then(|mut out| out.flush())
+                .map_err(Chip8Error::Output),
+            Target::PngSequence { .. } => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod capture_tests {
+    use super::*;
+
+    // frames and their delays in a GIF
+    fn decode(data: &[u8]) -> Vec<(Vec<u8>, u16)> {
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(data).unwrap();
+
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            frames.push((frame.buffer.to_vec(), frame.delay));
+        }
+        frames
+    }
+
+    #[test]
+    fn test_gif_two_colours() {
+        let mut display = Display::with_size(2, 1);
+        display.write_pixel(1, 2);
+
+        let mut writer = GifWriter::init(Vec::new(), &display, 2).unwrap();
+        writer.add_frame(&display).unwrap();
+        let data = writer.finish().unwrap();
+
+        // the plane 2 pixel uses the foreground colour
+        assert_eq!(decode(&data), vec![(vec![0, 0, 1, 1, 0, 0, 1, 1], 2)]);
+    }
+
+    #[test]
+    fn test_gif_timing() {
+        let mut display = Display::with_size(1, 1);
+        let mut writer = GifWriter::init(Vec::new(), &display, 1).unwrap();
+
+        // half a second dark, one lit frame, then half a second dark
+        for _ in 0..30 {
+            writer.add_frame(&display).unwrap();
+        }
+        display.write_pixel(0, 1);
+        writer.add_frame(&display).unwrap();
+        display.write_pixel(0, 0);
+        for _ in 0..30 {
+            writer.add_frame(&display).unwrap();
+        }
+
+        assert_eq!(writer.frames(), 61);
+        let frames = decode(&writer.finish().unwrap());
+
+        // the lit frame is too short to show, the dark ones around it merge
+        assert_eq!(frames, vec![(vec![0], 101)]);
+    }
+
+    #[test]
+    fn test_gif_merges_repeated_frames() {
+        let mut display = Display::with_size(1, 1);
+        let mut writer = GifWriter::init(Vec::new(), &display, 1).unwrap();
+
+        for frame in 0..120 {
+            display.write_pixel(0, frame / 60);
+            writer.add_frame(&display).unwrap();
+        }
+
+        let frames = decode(&writer.finish().unwrap());
+        assert_eq!(frames, vec![(vec![0], 100), (vec![1], 100)]);
+    }
+}
@@ -1,5 +1,17 @@
-use crate::{bus, cpu, display};
-use rand::prelude::*;
+use crate::{
+    audio, bus, cpu, display,
+    error::Chip8Error,
+    platform::{InstructionSet, Platform, PlatformConfig},
+    quirks::Quirks,
+    rng::{RandomModel, Rng},
+    savestate::{self, StateReader, StateWriter},
+    timing::{self, Timing},
+    trace::Tracer,
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
 
 pub const INSTRUCTIONS_PER_SECOND: u32 = 500;
 
@@ -10,51 +22,387 @@ pub enum EmulatorState {
     PAUSED,
 }
 
+/// What a call to `Chip8::step` did
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    /// Ran `opcode` from address `pc`
+    Instruction { pc: u16, opcode: u16 },
+    /// The frame had no instruction left, the timers ticked
+    FrameEnd,
+}
+
+/// Copy of the whole machine taken by `Chip8::snapshot`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    data: Vec<u8>,
+}
+
+impl Snapshot {
+    /// Snapshots share the save state format
+    pub fn from_bytes(data: Vec<u8>) -> Snapshot {
+        Snapshot { data }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+}
+
 pub struct Chip8 {
     pub cpu: cpu::CPU,
     pub bus: bus::Bus,
     pub state: EmulatorState,
+    pub config: PlatformConfig,
+    // set by Dxyn under the display wait quirk, cleared by the next timer tick
+    waiting_for_vblank: bool,
+    // the sound timer was still running at the last timer tick
+    sound_playing: bool,
+    // machine cycles left before the next 60 Hz interrupt, COSMAC VIP timing only
+    cycle_budget: i64,
+    // instructions owed to the next frame in 1/60 instruction, flat timing only
+    instruction_carry: u32,
+    // a frame started running and its timer tick is still to come
+    in_frame: bool,
+    // instructions left in the current frame, flat timing only
+    frame_instructions: u32,
+    pub rng: Rng,
+    // loaded by `load_rom`, kept for `reset`
+    rom: Vec<u8>,
+    // where the ROM came from, save state slots are stored next to it
+    rom_path: Option<PathBuf>,
+    // sees every instruction before it runs
+    tracer: Option<Tracer>,
 }
 
 impl Chip8 {
     pub fn init() -> Chip8 {
+        Chip8::with_config(PlatformConfig::default())
+    }
+
+    pub fn with_quirks(quirks: Quirks) -> Chip8 {
+        Chip8::with_config(PlatformConfig {
+            quirks,
+            ..PlatformConfig::default()
+        })
+    }
+
+    pub fn with_platform(platform: Platform) -> Chip8 {
+        Chip8::with_config(platform.config())
+    }
+
+    pub fn with_config(config: PlatformConfig) -> Chip8 {
         Chip8 {
             cpu: cpu::CPU::init(),
-            bus: bus::Bus::init(),
+            bus: bus::Bus::for_platform(&config),
             state: EmulatorState::Running,
+            config,
+            waiting_for_vblank: false,
+            sound_playing: false,
+            cycle_budget: 0,
+            instruction_carry: 0,
+            in_frame: false,
+            frame_instructions: 0,
+            rng: match config.seed {
+                Some(seed) => Rng::from_seed(seed),
+                None => Rng::from_entropy(),
+            },
+            rom: Vec::new(),
+            rom_path: None,
+            tracer: None,
+        }
+    }
+
+    pub fn run(&mut self) -> Result<(), Chip8Error> {
+        // Sprite already drawn this frame, nothing runs until the next tick.
+        // Nothing runs at all once 00FD has quit.
+        if self.waiting_for_vblank || self.state == EmulatorState::Quit {
+            return Ok(());
         }
+
+        let opcode = self.fetch()?;
+        self.trace(self.cpu.get_pc(), opcode);
+
+        self.cpu.increment_pc();
+        self.exec_instructions(opcode)
     }
 
-    pub fn run(&mut self) {
-        let first_byte = self.bus.ram_read_byte(self.cpu.get_pc()) as u16;
-        let second_byte = self.bus.ram_read_byte(self.cpu.get_pc() + 1) as u16;
+    /// Runs one 60 Hz frame worth of instructions and then ticks the timers.
+    /// Under flat timing that is `instructions_per_second / 60` instructions,
+    /// with the remainder carried over so the rate is exact over a second.
+    /// Under COSMAC VIP timing every opcode costs its machine cycles, and an
+    /// instruction still running when the interrupt is due finishes first,
+    /// its extra cycles coming out of the next frame. Finishes a frame that
+    /// `step` left half done.
+    pub fn run_frame(&mut self) -> Result<(), Chip8Error> {
+        while self.step()? != Step::FrameEnd {}
+        Ok(())
+    }
+
+    /// Runs the next instruction of the current frame, starting a new frame
+    /// if needed. Once the frame has no instruction left, ticks the timers
+    /// instead and returns `Step::FrameEnd`. Calling `step` until then does
+    /// the same as `run_frame`, so debuggers can stop in the middle of a frame.
+    /// Once 00FD has quit, the machine is halted and every call returns
+    /// `Step::FrameEnd` without running anything.
+    pub fn step(&mut self) -> Result<Step, Chip8Error> {
+        if self.state == EmulatorState::Quit {
+            return Ok(Step::FrameEnd);
+        }
+
+        if !self.in_frame {
+            self.begin_frame();
+        }
+
+        if !self.frame_has_instruction() {
+            self.end_frame();
+            return Ok(Step::FrameEnd);
+        }
+
+        let pc = self.cpu.get_pc();
+        let opcode = self.fetch()?;
+        self.trace(pc, opcode);
 
-        let opcode = (first_byte << 8) | second_byte;
+        match self.config.timing {
+            Timing::Flat => self.frame_instructions -= 1,
+            Timing::CosmacVip => {
+                self.cycle_budget -= i64::from(timing::vip_cycles(opcode, &self.cpu))
+            }
+        }
 
         self.cpu.increment_pc();
-        self.exec_instructions(opcode);
+        self.exec_instructions(opcode)?;
+        Ok(Step::Instruction { pc, opcode })
+    }
+
+    /// Address of the instruction the next `step` runs, `None` if it ends the frame
+    pub fn next_instruction(&self) -> Option<u16> {
+        let has_instruction = if self.in_frame {
+            self.frame_has_instruction()
+        } else {
+            // what `begin_frame` would give
+            let budget = match self.config.timing {
+                Timing::Flat => {
+                    i64::from((self.config.instructions_per_second + self.instruction_carry) / 60)
+                }
+                Timing::CosmacVip => self.cycle_budget + i64::from(timing::VIP_INTERPRETER_CYCLES),
+            };
+            budget > 0 && !self.waiting_for_vblank
+        };
+
+        has_instruction.then(|| self.cpu.get_pc())
+    }
+
+    /// True between a `step` that started a frame and the one that ends it
+    pub fn in_frame(&self) -> bool {
+        self.in_frame
+    }
+
+    fn begin_frame(&mut self) {
+        match self.config.timing {
+            Timing::Flat => {
+                let owed = self.config.instructions_per_second + self.instruction_carry;
+                self.instruction_carry = owed % 60;
+                self.frame_instructions = owed / 60;
+            }
+            Timing::CosmacVip => self.cycle_budget += i64::from(timing::VIP_INTERPRETER_CYCLES),
+        }
+
+        self.in_frame = true;
+    }
+
+    fn frame_has_instruction(&self) -> bool {
+        // Sprite already drawn this frame, nothing runs until the next tick
+        if self.waiting_for_vblank {
+            return false;
+        }
+
+        match self.config.timing {
+            Timing::Flat => self.frame_instructions > 0,
+            Timing::CosmacVip => self.cycle_budget > 0,
+        }
     }
 
-    pub fn load_rom(&mut self, data: Vec<u8>) {
+    fn end_frame(&mut self) {
+        // Dxyn sleeps through the rest of the frame
+        if self.config.timing == Timing::CosmacVip && self.waiting_for_vblank {
+            self.cycle_budget = self.cycle_budget.min(0);
+        }
+
+        self.frame_instructions = 0;
+        self.in_frame = false;
+        self.update_timer();
+
+        if let Some(tracer) = &mut self.tracer {
+            tracer.end_frame();
+        }
+    }
+
+    fn trace(&mut self, pc: u16, opcode: u16) {
+        if let Some(tracer) = &mut self.tracer {
+            tracer.trace(&self.cpu, pc, opcode);
+        }
+    }
+
+    pub fn load_rom(&mut self, data: Vec<u8>) -> Result<(), Chip8Error> {
         let offset = cpu::EXECUTION_INDEX;
+        let max = self.bus.ram_size() - offset as usize;
 
-        for (i, val) in data.into_iter().enumerate() {
-            self.bus.ram_write_byte(offset as u16 + i as u16, val)
+        if data.len() > max {
+            return Err(Chip8Error::RomTooLarge {
+                size: data.len(),
+                max,
+            });
         }
 
+        for (i, &val) in data.iter().enumerate() {
+            self.bus.ram_poke(offset + i as u16, val)?;
+        }
+        self.rom = data;
+
         // self.bus.ram_print()
+        Ok(())
+    }
+
+    pub fn load_rom_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Chip8Error> {
+        let data = fs::read(&path).map_err(Chip8Error::RomLoad)?;
+        self.load_rom(data)?;
+
+        self.rom_path = Some(path.as_ref().to_path_buf());
+        Ok(())
+    }
+
+    pub fn rom_path(&self) -> Option<&Path> {
+        self.rom_path.as_deref()
+    }
+
+    /// Last ROM given to `load_rom`
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    /// Powers the machine off and on again with the same ROM loaded. The
+    /// random number generator starts over from `config.seed`. The tracer
+    /// and watchpoints stay.
+    pub fn reset(&mut self) -> Result<(), Chip8Error> {
+        let mut fresh = Chip8::with_config(self.config);
+        fresh.load_rom(self.rom.clone())?;
+
+        for &watchpoint in self.bus.watchpoints() {
+            fresh.bus.add_watchpoint(watchpoint);
+        }
+        fresh.rom_path = self.rom_path.take();
+        fresh.tracer = self.tracer.take();
+
+        *self = fresh;
+        Ok(())
+    }
+
+    /// Writes every instruction from now on to `tracer`
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    /// Stops tracing and hands the tracer back so it can be finished
+    pub fn take_tracer(&mut self) -> Option<Tracer> {
+        self.tracer.take()
+    }
+
+    /// Serializes the whole machine: registers, stack, timers, memory,
+    /// screen, keypad, audio and random number generator
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = StateWriter::init();
+        savestate::write_header(&mut out, &self.config);
+
+        out.put_bool(self.waiting_for_vblank);
+        out.put_bool(self.sound_playing);
+        out.put_u64(self.cycle_budget as u64);
+        out.put_u32(self.instruction_carry);
+        out.put_bool(self.in_frame);
+        out.put_u32(self.frame_instructions);
+        self.cpu.save_state(&mut out);
+        self.bus.save_state(&mut out);
+        out.put_u64(self.rng.get_state());
+
+        out.into_bytes()
+    }
+
+    /// Restores a state from `save_state`. On error the machine is left untouched.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), Chip8Error> {
+        let mut input = StateReader::init(data);
+        savestate::check_header(&mut input, &self.config)?;
+
+        let waiting_for_vblank = input.get_bool()?;
+        let sound_playing = input.get_bool()?;
+        let cycle_budget = input.get_u64()? as i64;
+        let instruction_carry = input.get_u32()?;
+        let in_frame = input.get_bool()?;
+        let frame_instructions = input.get_u32()?;
+
+        let mut cpu = cpu::CPU::init();
+        cpu.load_state(&mut input)?;
+
+        let mut bus = bus::Bus::for_platform(&self.config);
+        bus.load_state(&mut input)?;
+
+        let rng_state = input.get_u64()?;
+        input.finish()?;
+
+        self.waiting_for_vblank = waiting_for_vblank;
+        self.sound_playing = sound_playing;
+        self.cycle_budget = cycle_budget;
+        self.instruction_carry = instruction_carry;
+        self.in_frame = in_frame;
+        self.frame_instructions = frame_instructions;
+        // watchpoints belong to the debugging session, not to the saved machine
+        for &watchpoint in self.bus.watchpoints() {
+            bus.add_watchpoint(watchpoint);
+        }
+
+        self.cpu = cpu;
+        self.bus = bus;
+        self.rng.set_state(rng_state);
+        Ok(())
+    }
+
+    /// Copies the machine so it can be put back later with `restore`
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            data: self.save_state(),
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), Chip8Error> {
+        self.load_state(&snapshot.data)
+    }
+
+    pub fn save_state_file<P: AsRef<Path>>(&self, path: P) -> Result<(), Chip8Error> {
+        fs::write(path, self.save_state()).map_err(Chip8Error::SaveStateFile)
+    }
+
+    pub fn load_state_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Chip8Error> {
+        let data = fs::read(path).map_err(Chip8Error::SaveStateFile)?;
+        self.load_state(&data)
     }
 
     pub fn update_timer(&mut self) {
-        if (self.cpu.get_delay_timer() > 0) {
+        self.waiting_for_vblank = false;
+        self.sound_playing = self.cpu.get_sound_timer() > 0;
+
+        if self.cpu.get_delay_timer() > 0 {
             self.cpu.decrease_delay_timer();
         }
 
-        if (self.cpu.get_sound_timer() > 0) {
+        if self.cpu.get_sound_timer() > 0 {
             self.cpu.decrease_sound_timer();
         }
     }
 
+    /// Whether the buzzer sounds for the frame that just ran, read before the
+    /// sound timer ticked down so `Fx18` with 1 still beeps for one frame
+    pub fn is_sound_playing(&self) -> bool {
+        self.sound_playing
+    }
+
     pub fn change_state(&mut self, state: EmulatorState) {
         self.state = state
     }
@@ -63,7 +411,7 @@ impl Chip8 {
         self.bus.display_get_buffer()
     }
 
-    pub fn exec_instructions(&mut self, opcode: u16) {
+    pub fn exec_instructions(&mut self, opcode: u16) -> Result<(), Chip8Error> {
         let left_nibble = (opcode & 0xF000) >> 12;
         let vx = ((opcode & 0x0F00) >> 8) as u8;
         let vy = ((opcode & 0x00F0) >> 4) as u8;
@@ -73,134 +421,161 @@ impl Chip8 {
 
         match left_nibble {
             0x0 => match opcode {
-                /// Clearing display
-                0x00E0 => {
-                    debug_opcodes(&opcode, "00E0", "Clear the display.");
+                // Clearing display
+                0x00E0 => self.bus.display_clear(),
 
-                    self.bus.display_clear()
+                // XO-CHIP: scroll the display up n pixels
+                0x00D1..=0x00DF if self.xochip() => {
+                    self.bus.display_scroll_up(n as usize);
                 }
 
+                // Return from a subroutine
                 0x00EE => {
-                    debug_opcodes(&opcode, "00EE", "Return from a subroutine.");
+                    self.cpu.decrease_sp()?;
 
-                    let sp = self.cpu.get_sp();
-                    let stack_value = self.cpu.get_stack_value(sp);
+                    let stack_value = self.cpu.get_stack_value(self.cpu.get_sp());
                     self.cpu.update_pc(stack_value);
-                    self.cpu.decrease_sp();
                 }
-                _ => println!("{}", "unknown op code"),
+
+                // SUPER-CHIP: scroll the display down n pixels
+                0x00C1..=0x00CF if self.superchip() => {
+                    self.bus.display_scroll_down(n as usize);
+                }
+
+                // SUPER-CHIP: scroll the display right 4 pixels
+                0x00FB if self.superchip() => {
+                    self.bus.display_scroll_right(4);
+                }
+
+                // SUPER-CHIP: scroll the display left 4 pixels
+                0x00FC if self.superchip() => {
+                    self.bus.display_scroll_left(4);
+                }
+
+                // SUPER-CHIP: exit the interpreter
+                0x00FD if self.superchip() => {
+                    self.change_state(EmulatorState::Quit);
+                }
+
+                // SUPER-CHIP: low resolution (64x32)
+                0x00FE if self.superchip() => {
+                    self.bus.display_set_hires(false);
+                }
+
+                // SUPER-CHIP: high resolution (128x64)
+                0x00FF if self.superchip() => {
+                    self.bus.display_set_hires(true);
+                }
+
+                _ => return Err(self.invalid_opcode(opcode)),
             },
 
-            /// 1nnn
-            /// set program couter to lowest 12 bit of opcode
+            // 1nnn
+            // set program couter to lowest 12 bit of opcode
             0x1 => {
-                debug_opcodes(
-                    &opcode,
-                    "1nnn",
-                    "set program couter to lowest 12 bit of opcode",
-                );
-
                 self.cpu.update_pc(nnn);
             }
 
-            /// 2nnn
+            // 2nnn
             0x2 => {
-                debug_opcodes(&opcode, "2nnn", "Call subroutine at nnn.");
-
-                self.cpu.increase_sp();
+                self.cpu.increase_sp()?;
                 self.cpu
-                    .set_stack_value(self.cpu.get_sp(), self.cpu.get_pc());
+                    .set_stack_value(self.cpu.get_sp() - 1, self.cpu.get_pc());
 
                 self.cpu.update_pc(nnn);
             }
 
-            /// 3xkk
+            // 3xkk
             0x3 => {
-                debug_opcodes(&opcode, "3xkk", "Skip next instruction if Vx = kk.");
-
                 if self.cpu.get_vreg_value(vx) == kk {
-                    self.cpu.update_pc(self.cpu.get_pc() + 2)
+                    self.skip_next_instruction()?;
                 }
             }
 
-            /// Skip next instruction if Vx != kk.
+            // Skip next instruction if Vx != kk.
             0x4 => {
-                debug_opcodes(&opcode, "4xkk", "Skip next instruction if Vx != kk");
-
                 if self.cpu.get_vreg_value(vx) != kk {
-                    self.cpu.update_pc(self.cpu.get_pc() + 2)
+                    self.skip_next_instruction()?;
                 }
             }
 
-            /// Skip next instruction if Vx = Vy
-            0x5 => {
-                debug_opcodes(&opcode, "5xy0", "Skip next instruction if Vx = Vy.");
+            0x5 => match n {
+                // Skip next instruction if Vx = Vy
+                0x0 => {
+                    if self.cpu.get_vreg_value(vx) == self.cpu.get_vreg_value(vy) {
+                        self.skip_next_instruction()?;
+                    }
+                }
+
+                // XO-CHIP: Store Vx through Vy in memory starting at location I.
+                0x2 if self.xochip() => {
+                    let i_reg = self.cpu.get_i_reg_value();
 
-                if self.cpu.get_vreg_value(vx) == self.cpu.get_vreg_value(vy) {
-                    self.cpu.update_pc(self.cpu.get_pc() + 2);
+                    for (offset, reg) in register_range(vx, vy).enumerate() {
+                        self.bus.ram_write_byte(
+                            i_reg.wrapping_add(offset as u16),
+                            self.cpu.get_vreg_value(reg),
+                        )?;
+                    }
                 }
-            }
 
-            ///Set Vx = kk
-            0x6 => {
-                debug_opcodes(&opcode, "6xkk", "Set Vx = kk.");
+                // XO-CHIP: Read Vx through Vy from memory starting at location I.
+                0x3 if self.xochip() => {
+                    let i_reg = self.cpu.get_i_reg_value();
 
-                self.cpu.set_vreg_value(vx, kk)
-            }
+                    for (offset, reg) in register_range(vx, vy).enumerate() {
+                        let value = self.bus.ram_read_byte(i_reg.wrapping_add(offset as u16))?;
+                        self.cpu.set_vreg_value(reg, value);
+                    }
+                }
 
-            ///Set Vx = Vx + kk.
-            0x7 => {
-                debug_opcodes(&opcode, "7xkk", "Set Vx = Vx + kk.");
+                _ => return Err(self.invalid_opcode(opcode)),
+            },
 
-                self.cpu
-                    .set_vreg_value(vx, self.cpu.get_vreg_value(vx).wrapping_add(kk))
-            }
+            // Set Vx = kk
+            0x6 => self.cpu.set_vreg_value(vx, kk),
 
-            /// Nested
-            0x8 => {
-                debug_opcodes(&opcode, "8xy0", "Set Vx = Vy.");
+            // Set Vx = Vx + kk.
+            0x7 => self
+                .cpu
+                .set_vreg_value(vx, self.cpu.get_vreg_value(vx).wrapping_add(kk)),
 
+            // Nested
+            0x8 => {
                 let op8 = opcode & 0x000F;
 
                 match op8 {
-                    /// Set Vx = Vy.
+                    // Set Vx = Vy.
                     0x0 => {
-                        debug_opcodes(&opcode, "8xy0", "Set Vx = Vy.");
-
                         self.cpu.set_vreg_value(vx, self.cpu.get_vreg_value(vy));
                     }
 
                     // Set Vx = Vx OR Vy
                     0x1 => {
-                        debug_opcodes(&opcode, "8xy1", "Set Vx = Vx OR Vy");
-
                         let value = self.cpu.get_vreg_value(vx) | self.cpu.get_vreg_value(vy);
 
                         self.cpu.set_vreg_value(vx, value);
+                        self.reset_vf();
                     }
 
-                    /// Set Vx = Vx AND Vy.
+                    // Set Vx = Vx AND Vy.
                     0x2 => {
-                        debug_opcodes(&opcode, "8xy2", "Set Vx = Vx AND Vy");
-
                         let value = self.cpu.get_vreg_value(vx) & self.cpu.get_vreg_value(vy);
 
                         self.cpu.set_vreg_value(vx, value);
+                        self.reset_vf();
                     }
 
-                    /// Set Vx = Vx XOR Vy.
+                    // Set Vx = Vx XOR Vy.
                     0x3 => {
-                        debug_opcodes(&opcode, "8xy3", "Set Vx = Vx XOR Vy.");
-
                         let value = self.cpu.get_vreg_value(vx) ^ self.cpu.get_vreg_value(vy);
 
                         self.cpu.set_vreg_value(vx, value);
+                        self.reset_vf();
                     }
 
-                    ///Set Vx = Vx + Vy, set VF = carry.
+                    // Set Vx = Vx + Vy, set VF = carry.
                     0x4 => {
-                        debug_opcodes(&opcode, "8xy4", "Set Vx = Vx + Vy, set VF = carry.");
-
                         let result =
                             self.cpu.get_vreg_value(vx) as u16 + self.cpu.get_vreg_value(vy) as u16;
 
@@ -212,8 +587,6 @@ impl Chip8 {
 
                     // Set Vx = Vx - Vy, set VF = NOT borrow.
                     0x5 => {
-                        debug_opcodes(&opcode, "8xy5", "Set Vx = Vx - Vy, set VF = NOT borrow");
-
                         let vf = if self.cpu.get_vreg_value(vx) >= self.cpu.get_vreg_value(vy) {
                             1
                         } else {
@@ -229,25 +602,18 @@ impl Chip8 {
                         self.cpu.set_vreg_value(0xF, vf);
                     }
 
-                    //Set Vx = Vx SHR 1.
-                    //wrong
+                    // Set Vx = Vx SHR 1.
                     0x6 => {
-                        debug_opcodes(&opcode, "8xy6", "Set Vx = Vx SHR 1.");
-
-                        // Store the least significant bit of Vx in VF
-                        self.cpu
-                            .set_vreg_value(0xF, self.cpu.get_vreg_value(vx) & 0x1);
+                        let source = self.shift_source(vx, vy);
 
-                        // Shift Vx 1 bit to the right
-                        self.cpu
-                            .set_vreg_value(vx, self.cpu.get_vreg_value(vx) >> 1);
+                        // Shift 1 bit to the right, the bit shifted out goes to VF.
+                        // VF is written last so it wins when x is F
+                        self.cpu.set_vreg_value(vx, source >> 1);
+                        self.cpu.set_vreg_value(0xF, source & 0x1);
                     }
 
-                    /// Set Vx = Vy - Vx, set VF = NOT borrow.
-                    // TODO not correct
+                    // Set Vx = Vy - Vx, set VF = NOT borrow.
                     0x7 => {
-                        debug_opcodes(&opcode, "8xy7", "Set Vx = Vy - Vx, set VF = NOT borrow.");
-                        // THINK :- might want to clamp it to zero if it goes below then 0
                         let value = self
                             .cpu
                             .get_vreg_value(vy)
@@ -264,137 +630,154 @@ impl Chip8 {
                     }
 
                     // Set Vx = Vx SHL 1.
-                    // wrong
                     0xE => {
-                        debug_opcodes(&opcode, "8xyE", "Set Vx = Vx SHL 1.");
+                        let source = self.shift_source(vx, vy);
 
-                        self.cpu
-                            .set_vreg_value(0xF, self.cpu.get_vreg_value(vx) >> 7);
-
-                        self.cpu
-                            .set_vreg_value(vx, self.cpu.get_vreg_value(vx) << 1);
+                        self.cpu.set_vreg_value(vx, source << 1);
+                        self.cpu.set_vreg_value(0xF, source >> 7);
                     }
 
-                    _ => println!("unknown opcode"),
+                    _ => return Err(self.invalid_opcode(opcode)),
                 }
             }
 
-            /// Skip next instruction if Vx != Vy.
+            // Skip next instruction if Vx != Vy.
             0x9 => {
                 if self.cpu.get_vreg_value(vx) != self.cpu.get_vreg_value(vy) {
-                    self.cpu.update_pc(self.cpu.get_pc() + 2);
+                    self.skip_next_instruction()?;
                 }
             }
 
-            /// Set I = nnn.
-            0xA => {
-                debug_opcodes(&opcode, "Annn", "Set I = nnn.");
-                self.cpu.set_i_reg_value(opcode & 0x0FFF)
-            }
+            // Set I = nnn.
+            0xA => self.cpu.set_i_reg_value(opcode & 0x0FFF),
 
-            /// Jump to location nnn + V0.
+            // Jump to location nnn + V0.
             0xB => {
-                debug_opcodes(&opcode, "Bnnn", "Jump to location nnn + V0.");
-
-                let addr = self.cpu.get_vreg_value(0) as u16 + nnn;
+                // CHIP-48 and SUPER-CHIP read this as Bxnn, jump to xnn + Vx
+                let offset_reg = if self.config.quirks.jump_uses_vx {
+                    vx
+                } else {
+                    0
+                };
+
+                let addr = self.cpu.get_vreg_value(offset_reg) as u16 + nnn;
                 self.cpu.update_pc(addr);
             }
 
-            /// Set Vx = random byte AND kk.
+            // Set Vx = random byte AND kk.
             0xC => {
-                debug_opcodes(&opcode, "Cxkk", "Set Vx = random byte AND kk");
-
-                let value = gen_random_byte() & kk as u8;
+                let random = match self.config.random_model {
+                    RandomModel::SplitMix => self.rng.next_byte(),
+                    RandomModel::PageSum => {
+                        // the machine reading its own page, not the ROM reading data
+                        let bus = &self.bus;
+                        self.rng
+                            .next_page_sum_byte(|pointer| bus.ram_peek(u16::from(pointer)))?
+                    }
+                };
+                let value = random & kk;
 
                 self.cpu.set_vreg_value(vx, value);
             }
 
-            /// Display n-byte sprite starting at memory location I at (Vx, Vy), set VF = collision.
+            // Display n-byte sprite starting at memory location I at (Vx, Vy), set VF = collision.
             0xD => {
-                debug_opcodes(&opcode, "Dxyn", "Display n-byte sprite starting at memory location I at (Vx, Vy), set VF = collision.");
-
-                let x_reg = self.cpu.get_vreg_value(vx);
-                let y_reg = self.cpu.get_vreg_value(vy);
-                let i_addr = self.cpu.get_i_reg_value();
-
-                // reading n bytes from memory starting at i_addr
-                for row in 0..n {
-                    // extracting bytes one by one
-                    let sprite_byte = self.bus.ram_read_byte(i_addr + row as u16);
-
-                    // each byte is made up of 8 bits. it loop over each bytes
-                    // XOR each pixel. it means if the current pixel is 1 it will set it to zero
-                    for col in 0..8 {
-                        // modulo operator dealing with oveflowing. it used to wrap the value.
-                        let pixel_x = (x_reg + col) % display::DISPLAY_WIDTH as u8;
-                        let pixel_y = (y_reg + row) % display::DISPLAY_HEIGHT as u8;
-
-                        // basic way to get correct coordinates from 1d array
-                        let index = pixel_x as usize + (pixel_y as usize * display::DISPLAY_WIDTH);
-                        let current_pixel = self.bus.display_get_pixel(index);
-
-                        // XOR each bit with current pixel and updating the display
-                        let value = current_pixel ^ (sprite_byte as u32 >> (7 - col)) & 0x1;
-                        self.bus.display_write_pixel(index, value);
-
-                        if value == 1 {
-                            self.cpu.set_vreg_value(0xF, 1);
-                        }
+                // SUPER-CHIP draws a 16x16 sprite for Dxy0, two bytes per row
+                let (sprite_width, rows) = if n == 0 && self.superchip() {
+                    (16, 16)
+                } else {
+                    (8, n as usize)
+                };
+
+                let x_reg = self.cpu.get_vreg_value(vx) as usize;
+                let y_reg = self.cpu.get_vreg_value(vy) as usize;
+                let mut sprite_addr = self.cpu.get_i_reg_value();
+                let selected_planes = self.bus.display_selected_planes();
+
+                let mut collision = false;
+
+                // XO-CHIP stores the sprite for each selected plane one after another
+                for plane in [display::PLANE_1, display::PLANE_2] {
+                    if selected_planes & plane == 0 {
+                        continue;
                     }
+
+                    collision |=
+                        self.draw_sprite(plane, sprite_addr, x_reg, y_reg, sprite_width, rows)?;
+                    sprite_addr = sprite_addr.wrapping_add((rows * sprite_width / 8) as u16);
                 }
 
-                // println!("{:?}", self.bus.display_get_buffer())
+                self.cpu.set_vreg_value(0xF, collision as u8);
+
+                if self.config.quirks.display_wait || self.config.timing == Timing::CosmacVip {
+                    self.waiting_for_vblank = true;
+                }
             }
 
-            /// Skip next instruction if key with the value of Vx is pressed.
+            // Skip next instruction if key with the value of Vx is pressed.
             0xE => {
                 let op_e = opcode & 0x000F;
                 match op_e {
+                    // Ex9E: skip if the key is pressed
                     0xE => {
-                        debug_opcodes(
-                            &opcode,
-                            "Ex9E",
-                            "Skip next instruction if key with the value of Vx is pressed.",
-                        );
-
                         let x_reg = self.cpu.get_vreg_value(vx);
 
                         if self.bus.is_key_pressed(x_reg as usize) {
-                            self.cpu.increment_pc();
+                            self.skip_next_instruction()?;
                         }
                     }
 
+                    // ExA1: skip if the key is not pressed
                     0x1 => {
-                        debug_opcodes(
-                            &opcode,
-                            "ExA1",
-                            "Skip next instruction if key with the value of Vx is pressed.",
-                        );
-
                         let x_reg = self.cpu.get_vreg_value(vx);
 
                         if !self.bus.is_key_pressed(x_reg as usize) {
-                            self.cpu.increment_pc();
+                            self.skip_next_instruction()?;
                         }
                     }
 
-                    _ => println!("unknown opcode"),
+                    _ => return Err(self.invalid_opcode(opcode)),
                 }
             }
 
             0xF => {
                 let op_e = opcode & 0x00FF;
                 match op_e {
-                    /// Set Vx = delay timer value.
-                    0x07 => {
-                        debug_opcodes(&opcode, "Fx07", "Set Vx = delay timer value.");
+                    // XO-CHIP: Set I = the 16 bit address in the next two bytes.
+                    0x00 if vx == 0 && self.xochip() => {
+                        let pc = self.cpu.get_pc();
+                        // part of the instruction, not data
+                        let high = self.bus.ram_peek(pc)? as u16;
+                        let low = self.bus.ram_peek(pc.wrapping_add(1))? as u16;
+
+                        self.cpu.set_i_reg_value((high << 8) | low);
+                        self.cpu.increment_pc();
+                    }
+
+                    // XO-CHIP: Select the bitplanes drawn to, x is a mask of planes 1 and 2.
+                    0x01 if self.xochip() => {
+                        self.bus.display_select_planes(vx as u32);
+                    }
+
+                    // XO-CHIP: Load the 16 byte audio pattern starting at location I.
+                    0x02 if vx == 0 && self.xochip() => {
+                        let i_reg = self.cpu.get_i_reg_value();
+                        let mut pattern = [0; audio::PATTERN_SIZE];
+
+                        for (i, byte) in pattern.iter_mut().enumerate() {
+                            *byte = self.bus.ram_read_byte(i_reg.wrapping_add(i as u16))?;
+                        }
+
+                        self.bus.audio_set_pattern(pattern);
+                    }
 
+                    // Set Vx = delay timer value.
+                    0x07 => {
                         self.cpu.set_vreg_value(vx, self.cpu.get_delay_timer());
                     }
 
-                    /// Wait for a key press, store the value of the key in Vx.
+                    // Wait for a key press, store the value of the key in Vx.
                     0x0A => {
-                        debug_opcodes(&opcode, "Fx0A", "unimplemented");
                         let mut is_key_pressed = false;
 
                         for i in 0..self.bus.get_keypad().len() {
@@ -411,49 +794,46 @@ impl Chip8 {
                         }
                     }
 
-                    /// Set delay timer = Vx.
+                    // Set delay timer = Vx.
                     0x15 => {
-                        debug_opcodes(&opcode, "Fx15", "Set delay timer = Vx.");
-
                         self.cpu.set_delay_timer(self.cpu.get_vreg_value(vx));
                     }
 
-                    ///Set sound timer = Vx.
+                    // Set sound timer = Vx.
                     0x18 => {
-                        debug_opcodes(&opcode, "Fx18", "Set sound timer = Vx.");
-
                         self.cpu.set_sound_timer(self.cpu.get_vreg_value(vx));
                     }
 
-                    /// Set I = I + Vx.
-                    0x1E => {
-                        debug_opcodes(&opcode, "Fx1E", "Set I = I + Vx.");
-
-                        self.cpu.set_i_reg_value(
-                            self.cpu
-                                .get_i_reg_value()
-                                .wrapping_add(self.cpu.get_vreg_value(vx) as u16),
-                        )
-                    }
+                    // Set I = I + Vx.
+                    0x1E => self.cpu.set_i_reg_value(
+                        self.cpu
+                            .get_i_reg_value()
+                            .wrapping_add(self.cpu.get_vreg_value(vx) as u16),
+                    ),
 
-                    /// Set I = location of sprite for digit Vx.
+                    // Set I = location of sprite for digit Vx.
                     0x29 => {
-                        debug_opcodes(&opcode, "Fx29", "Set I = location of sprite for digit Vx");
+                        let digit = (self.cpu.get_vreg_value(vx) & 0xF) as u16;
 
-                        let digit = self.cpu.get_vreg_value(vx);
+                        self.cpu
+                            .set_i_reg_value(crate::ram::FONTSET_START_ADDRESS + 5 * digit)
+                    }
+
+                    // SUPER-CHIP: Set I = location of the 8x10 sprite for digit Vx.
+                    0x30 if self.superchip() => {
+                        let digit = (self.cpu.get_vreg_value(vx) & 0xF) as u16;
 
                         self.cpu
-                            .set_i_reg_value(crate::ram::FONTSET_START_ADDRESS + (5 * digit) as u16)
+                            .set_i_reg_value(crate::ram::BIG_FONTSET_START_ADDRESS + 10 * digit)
                     }
 
-                    /// Store BCD representation of Vx in memory locations I, I+1, and I+2.
-                    0x33 => {
-                        debug_opcodes(
-                            &opcode,
-                            "Fx33",
-                            "Store BCD representation of Vx in memory locations I, I+1, and I+2",
-                        );
+                    // XO-CHIP: Set the audio pitch register = Vx.
+                    0x3A if self.xochip() => {
+                        self.bus.audio_set_pitch(self.cpu.get_vreg_value(vx));
+                    }
 
+                    // Store BCD representation of Vx in memory locations I, I+1, and I+2.
+                    0x33 => {
                         let value = self.cpu.get_vreg_value(vx);
 
                         // Extract hundreds, tens, and units digits
@@ -463,82 +843,221 @@ impl Chip8 {
 
                         let i_reg = self.cpu.get_i_reg_value();
 
-                        self.bus.ram_write_byte(i_reg, hundreds);
-                        self.bus.ram_write_byte(i_reg + 1, tens);
-                        self.bus.ram_write_byte(i_reg + 2, units);
+                        self.bus.ram_write_byte(i_reg, hundreds)?;
+                        self.bus.ram_write_byte(i_reg.wrapping_add(1), tens)?;
+                        self.bus.ram_write_byte(i_reg.wrapping_add(2), units)?;
                     }
 
-                    /// Store registers V0 through Vx in memory starting at location I.
+                    // Store registers V0 through Vx in memory starting at location I.
                     0x55 => {
-                        debug_opcodes(
-                            &opcode,
-                            "Fx55",
-                            "Store registers V0 through Vx in memory starting at location I.",
-                        );
-
                         let i_reg = self.cpu.get_i_reg_value();
 
                         for i in 0..=vx {
-                            self.bus
-                                .ram_write_byte(i_reg + i as u16, self.cpu.get_vreg_value(i));
+                            self.bus.ram_write_byte(
+                                i_reg.wrapping_add(i as u16),
+                                self.cpu.get_vreg_value(i),
+                            )?;
                         }
+
+                        self.increment_i_after_load_store(vx);
                     }
 
-                    /// Read registers V0 through Vx from memory starting at location I.
+                    // Read registers V0 through Vx from memory starting at location I.
                     0x65 => {
-                        debug_opcodes(
-                            &opcode,
-                            "Fx65",
-                            "Read registers V0 through Vx from memory starting at location I.",
-                        );
-
                         let i_reg = self.cpu.get_i_reg_value();
 
                         for i in 0..=vx {
-                            self.cpu
-                                .set_vreg_value(i, self.bus.ram_read_byte(i_reg + i as u16))
+                            let value = self.bus.ram_read_byte(i_reg.wrapping_add(i as u16))?;
+                            self.cpu.set_vreg_value(i, value);
                         }
+
+                        self.increment_i_after_load_store(vx);
                     }
 
-                    _ => println!("unknown opcode"),
+                    // SUPER-CHIP: Store V0 through Vx in the RPL user flags.
+                    0x75 if self.superchip() => {
+                        for i in 0..=vx {
+                            self.cpu.set_rpl_flag(i, self.cpu.get_vreg_value(i));
+                        }
+                    }
+
+                    // SUPER-CHIP: Read V0 through Vx from the RPL user flags.
+                    0x85 if self.superchip() => {
+                        for i in 0..=vx {
+                            self.cpu.set_vreg_value(i, self.cpu.get_rpl_flag(i));
+                        }
+                    }
+
+                    _ => return Err(self.invalid_opcode(opcode)),
                 }
             }
 
-            _ => println!("Unknown opcode"),
+            _ => return Err(self.invalid_opcode(opcode)),
         }
+
+        Ok(())
+    }
+
+    // SUPER-CHIP instructions are also part of XO-CHIP
+    fn superchip(&self) -> bool {
+        self.config.instruction_set != InstructionSet::Chip8
+    }
+
+    fn xochip(&self) -> bool {
+        self.config.instruction_set == InstructionSet::XoChip
+    }
+
+    // opcode at the program counter, which is left where it is
+    fn fetch(&mut self) -> Result<u16, Chip8Error> {
+        let first_byte = self.bus.ram_peek(self.cpu.get_pc())? as u16;
+        let second_byte = self.bus.ram_peek(self.cpu.get_pc().wrapping_add(1))? as u16;
+
+        Ok((first_byte << 8) | second_byte)
+    }
+
+    // skips have to step over both halves of the 4 byte XO-CHIP F000 nnnn
+    fn skip_next_instruction(&mut self) -> Result<(), Chip8Error> {
+        if self.xochip() {
+            let pc = self.cpu.get_pc();
+            let next = (self.bus.ram_peek(pc)? as u16) << 8
+                | self.bus.ram_peek(pc.wrapping_add(1))? as u16;
+
+            if next == 0xF000 {
+                self.cpu.increment_pc();
+            }
+        }
+
+        self.cpu.increment_pc();
+        Ok(())
     }
-}
 
-fn gen_random_byte() -> u8 {
-    let mut rng = rand::thread_rng();
-    rng.gen_range(0..=255)
+    /// Draws a sprite into a single bitplane, returns true on collision
+    fn draw_sprite(
+        &mut self,
+        plane: u32,
+        sprite_addr: u16,
+        x: usize,
+        y: usize,
+        sprite_width: usize,
+        rows: usize,
+    ) -> Result<bool, Chip8Error> {
+        let width = self.bus.display_logical_width();
+        let height = self.bus.display_logical_height();
+        let bytes_per_row = sprite_width / 8;
+
+        // the starting position always wraps, only the sprite itself may be clipped
+        let x = x % width;
+        let y = y % height;
+
+        let mut collision = false;
+
+        // reading n rows from memory starting at sprite_addr
+        for row in 0..rows {
+            let mut pixel_y = y + row;
+            if pixel_y >= height {
+                if self.config.quirks.clip_sprites {
+                    break;
+                }
+                pixel_y %= height;
+            }
+
+            // extracting the bytes of this row
+            let mut sprite_row: u16 = 0;
+            for byte in 0..bytes_per_row {
+                let addr = sprite_addr.wrapping_add((row * bytes_per_row + byte) as u16);
+                sprite_row = (sprite_row << 8) | self.bus.ram_read_byte(addr)? as u16;
+            }
+
+            // each row is made up of 8 or 16 bits. it loop over each bits
+            // XOR each pixel. it means if the current pixel is 1 it will set it to zero
+            for col in 0..sprite_width {
+                if (sprite_row >> (sprite_width - 1 - col)) & 0x1 == 0 {
+                    continue;
+                }
+
+                let mut pixel_x = x + col;
+                if pixel_x >= width {
+                    if self.config.quirks.clip_sprites {
+                        break;
+                    }
+                    pixel_x %= width;
+                }
+
+                // turning a lit pixel off is a collision
+                if self.bus.display_toggle_pixel(pixel_x, pixel_y, plane) {
+                    collision = true;
+                }
+            }
+        }
+
+        Ok(collision)
+    }
+
+    // 8xy1, 8xy2 and 8xy3 clobber VF on the COSMAC VIP
+    fn reset_vf(&mut self) {
+        if self.config.quirks.vf_reset {
+            self.cpu.set_vreg_value(0xF, 0);
+        }
+    }
+
+    // value 8xy6 and 8xyE shift, Vy on the COSMAC VIP and Vx everywhere else
+    fn shift_source(&self, vx: u8, vy: u8) -> u8 {
+        if self.config.quirks.shift_uses_vy {
+            self.cpu.get_vreg_value(vy)
+        } else {
+            self.cpu.get_vreg_value(vx)
+        }
+    }
+
+    fn increment_i_after_load_store(&mut self, vx: u8) {
+        if self.config.quirks.load_store_increments_i {
+            let i_reg = self.cpu.get_i_reg_value();
+            self.cpu.set_i_reg_value(i_reg.wrapping_add(vx as u16 + 1));
+        }
+    }
+
+    // the program counter has already moved past the opcode when it runs
+    fn invalid_opcode(&self, opcode: u16) -> Chip8Error {
+        Chip8Error::InvalidOpcode {
+            opcode,
+            pc: self.cpu.get_pc().wrapping_sub(2),
+        }
+    }
 }
 
-fn debug_opcodes(address: &u16, opcode: &str, desc: &str) {
-    // println!(
-    //     "Address: {}, Opcode: {}, Desc: {}",
-    //     format!("{:X}", address),
-    //     opcode,
-    //     desc
-    // )
+// registers x through y, counting down when x > y
+fn register_range(vx: u8, vy: u8) -> Box<dyn Iterator<Item = u8>> {
+    if vx <= vy {
+        Box::new(vx..=vy)
+    } else {
+        Box::new((vy..=vx).rev())
+    }
 }
 
 // Test
 #[cfg(test)]
 mod chip8_tests {
-    use crate::chip8::Chip8;
+    use crate::chip8::{Chip8, EmulatorState, Step};
+    use crate::display;
+    use crate::error::Chip8Error;
+    use crate::platform::Platform;
+    use crate::platform::PlatformConfig;
+    use crate::quirks::Quirks;
+    use crate::rng::RandomModel;
+    use crate::savestate;
+    use crate::timing::{self, Timing};
+    use crate::watch::{WatchKind, Watchpoint};
 
     #[test]
     fn test_return_instruction() {
         let mut chip8 = Chip8::init();
 
         // mocking
-        chip8.cpu.increase_sp();
-        let sp = chip8.cpu.get_sp();
-        chip8.cpu.set_stack_value(sp, 302);
+        chip8.cpu.set_stack_value(0, 302);
+        chip8.cpu.increase_sp().unwrap();
 
         //result
-        chip8.exec_instructions(0x00EE);
+        chip8.exec_instructions(0x00EE).unwrap();
         assert_eq!(chip8.cpu.get_sp(), 0);
         assert_eq!(chip8.cpu.get_pc(), 302);
     }
@@ -549,7 +1068,7 @@ mod chip8_tests {
 
         //mock
         let addr = 0x1300 & 0x0FFF;
-        chip8.exec_instructions(0x1300);
+        chip8.exec_instructions(0x1300).unwrap();
 
         assert_eq!(chip8.cpu.get_pc(), addr);
     }
@@ -562,11 +1081,11 @@ mod chip8_tests {
 
         //mock
         chip8.cpu.update_pc(23);
-        chip8.exec_instructions(0x2300);
+        chip8.exec_instructions(0x2300).unwrap();
 
         assert_eq!(chip8.cpu.get_sp(), 1);
         assert_eq!(chip8.cpu.get_pc(), addr);
-        assert_eq!(chip8.cpu.get_stack_value(chip8.cpu.get_sp()), 23);
+        assert_eq!(chip8.cpu.get_stack_value(0), 23);
     }
 
     #[test]
@@ -581,12 +1100,12 @@ mod chip8_tests {
 
         // kk is not equal to the value at Vx register
         chip8.cpu.set_vreg_value(vx as u8, (kk + 1) as u8);
-        chip8.exec_instructions(opcode);
+        chip8.exec_instructions(opcode).unwrap();
         assert_eq!(chip8.cpu.get_pc(), 0x2000);
 
         // if kk is equal to the value at Vx register
         chip8.cpu.set_vreg_value(vx as u8, kk as u8);
-        chip8.exec_instructions(opcode);
+        chip8.exec_instructions(opcode).unwrap();
         assert_eq!(chip8.cpu.get_pc(), 0x2000 + 2);
     }
 
@@ -602,12 +1121,12 @@ mod chip8_tests {
 
         chip8.cpu.set_vreg_value(vx as u8, kk as u8);
         chip8.cpu.update_pc(0x2000);
-        chip8.exec_instructions(opcode);
+        chip8.exec_instructions(opcode).unwrap();
         assert_eq!(chip8.cpu.get_pc(), 0x2000);
 
         //if kk is not equal to register at vx
         chip8.cpu.set_vreg_value(vx as u8, (kk + 2) as u8);
-        chip8.exec_instructions(opcode);
+        chip8.exec_instructions(opcode).unwrap();
         assert_eq!(chip8.cpu.get_pc(), 0x2000 + 2);
     }
 
@@ -622,13 +1141,13 @@ mod chip8_tests {
         //if Vx is not equal to Vy
         chip8.cpu.set_vreg_value(vx as u8, 5);
         chip8.cpu.set_vreg_value(vy as u8, 8);
-        chip8.exec_instructions(opcode);
+        chip8.exec_instructions(opcode).unwrap();
         assert_eq!(chip8.cpu.get_pc(), 0x2000, "should not be equal");
 
         //if Vx is equal to Vy
         chip8.cpu.set_vreg_value(vx as u8, 5);
         chip8.cpu.set_vreg_value(vy as u8, 5);
-        chip8.exec_instructions(opcode);
+        chip8.exec_instructions(opcode).unwrap();
         assert_eq!(chip8.cpu.get_pc(), 0x2000 + 2, "should be equal");
     }
 
@@ -640,7 +1159,7 @@ mod chip8_tests {
         let vx = (opcode & 0x0F00) >> 8;
         let value = opcode & 0x00ff;
 
-        chip8.exec_instructions(opcode);
+        chip8.exec_instructions(opcode).unwrap();
         assert_eq!(chip8.cpu.get_vreg_value(vx as u8), value as u8);
     }
 
@@ -652,7 +1171,7 @@ mod chip8_tests {
         let value = opcode & 0x00ff;
 
         chip8.cpu.set_vreg_value(vx as u8, 4);
-        chip8.exec_instructions(opcode);
+        chip8.exec_instructions(opcode).unwrap();
         assert_eq!(chip8.cpu.get_vreg_value(vx as u8), value as u8 + 4);
     }
 
@@ -664,7 +1183,7 @@ mod chip8_tests {
         let vy = ((opcode & 0x00F0) >> 4) as u8;
 
         chip8.cpu.set_vreg_value(vy, 8);
-        chip8.exec_instructions(opcode);
+        chip8.exec_instructions(opcode).unwrap();
 
         assert_eq!(chip8.cpu.get_vreg_value(vx), 8);
     }
@@ -681,7 +1200,7 @@ mod chip8_tests {
 
         let value = chip8.cpu.get_vreg_value(vx) | chip8.cpu.get_vreg_value(vy);
 
-        chip8.exec_instructions(opcode);
+        chip8.exec_instructions(opcode).unwrap();
         assert_eq!(chip8.cpu.get_vreg_value(vx), value);
     }
 
@@ -697,7 +1216,7 @@ mod chip8_tests {
 
         let value = chip8.cpu.get_vreg_value(vx) & chip8.cpu.get_vreg_value(vy);
 
-        chip8.exec_instructions(opcode);
+        chip8.exec_instructions(opcode).unwrap();
         assert_eq!(chip8.cpu.get_vreg_value(vx), value);
     }
 
@@ -713,7 +1232,7 @@ mod chip8_tests {
 
         let value = chip8.cpu.get_vreg_value(vx) ^ chip8.cpu.get_vreg_value(vy);
 
-        chip8.exec_instructions(opcode);
+        chip8.exec_instructions(opcode).unwrap();
         assert_eq!(chip8.cpu.get_vreg_value(vx), value);
     }
 
@@ -729,9 +1248,7 @@ mod chip8_tests {
 
         let result = chip8.cpu.get_vreg_value(vx) as u16 + chip8.cpu.get_vreg_value(vy) as u16;
 
-        let vf = if result > 255 { 1 } else { 0 };
-
-        chip8.exec_instructions(opcode);
+        chip8.exec_instructions(opcode).unwrap();
         assert_eq!(chip8.cpu.get_vreg_value(vx), (result & 0x00ff) as u8);
         assert_eq!(chip8.cpu.get_vreg_value(0xF), 1, "should be 1")
     }
@@ -748,9 +1265,7 @@ mod chip8_tests {
 
         let result = chip8.cpu.get_vreg_value(vx) as u16 + chip8.cpu.get_vreg_value(vy) as u16;
 
-        let vf = if result > 255 { 1 } else { 0 };
-
-        chip8.exec_instructions(opcode);
+        chip8.exec_instructions(opcode).unwrap();
         assert_eq!(chip8.cpu.get_vreg_value(vx), (result & 0x00ff) as u8);
         assert_eq!(chip8.cpu.get_vreg_value(0xF), 0, "should be 0")
     }
@@ -770,7 +1285,7 @@ mod chip8_tests {
             .get_vreg_value(vx)
             .wrapping_sub(chip8.cpu.get_vreg_value(vy));
 
-        chip8.exec_instructions(opcode);
+        chip8.exec_instructions(opcode).unwrap();
 
         assert_eq!(chip8.cpu.get_vreg_value(vx), value);
         assert_eq!(chip8.cpu.get_vreg_value(0xF), 1, "0xF should be 1");
@@ -790,7 +1305,7 @@ mod chip8_tests {
             .get_vreg_value(vx)
             .wrapping_sub(chip8.cpu.get_vreg_value(vy));
 
-        chip8.exec_instructions(opcode);
+        chip8.exec_instructions(opcode).unwrap();
 
         assert_eq!(chip8.cpu.get_vreg_value(vx), value);
         assert_eq!(chip8.cpu.get_vreg_value(0xF), 0, "0xF should be 0");
@@ -804,7 +1319,7 @@ mod chip8_tests {
 
         chip8.cpu.set_vreg_value(vx, 1);
 
-        chip8.exec_instructions(opcode);
+        chip8.exec_instructions(opcode).unwrap();
 
         assert_eq!(chip8.cpu.get_vreg_value(0xF), 1, "Vf should be 1");
         assert_eq!(chip8.cpu.get_vreg_value(vx), 1 >> 1);
@@ -817,7 +1332,7 @@ mod chip8_tests {
         let vx = ((opcode & 0x0F00) >> 8) as u8;
 
         chip8.cpu.set_vreg_value(vx, 4);
-        chip8.exec_instructions(opcode);
+        chip8.exec_instructions(opcode).unwrap();
 
         assert_eq!(chip8.cpu.get_vreg_value(vx), 4 >> 1);
         assert_eq!(chip8.cpu.get_vreg_value(0xF), 0, "Vf should be 0")
@@ -833,7 +1348,7 @@ mod chip8_tests {
         chip8.cpu.set_vreg_value(vx, 4);
         chip8.cpu.set_vreg_value(vy, 5);
 
-        chip8.exec_instructions(opcode);
+        chip8.exec_instructions(opcode).unwrap();
         assert_eq!(chip8.cpu.get_vreg_value(0xF), 1, "0xF should be 1");
         assert_eq!(chip8.cpu.get_vreg_value(vx), 5 - 4);
     }
@@ -847,7 +1362,7 @@ mod chip8_tests {
         chip8.cpu.set_vreg_value(vx, 5);
         chip8.cpu.set_vreg_value(vy, 4);
 
-        chip8.exec_instructions(opcode);
+        chip8.exec_instructions(opcode).unwrap();
         assert_eq!(chip8.cpu.get_vreg_value(0xF), 0, "0xF should be 0");
         assert_eq!(chip8.cpu.get_vreg_value(vx), 4_u8.wrapping_sub(5));
     }
@@ -859,7 +1374,7 @@ mod chip8_tests {
         let vx = ((opcode & 0x0F00) >> 8) as u8;
 
         chip8.cpu.set_vreg_value(vx, 128);
-        chip8.exec_instructions(opcode);
+        chip8.exec_instructions(opcode).unwrap();
 
         assert_eq!(chip8.cpu.get_vreg_value(0xF), 1, "0xF should be 1");
         assert_eq!(chip8.cpu.get_vreg_value(vx), 128 << 1);
@@ -872,7 +1387,7 @@ mod chip8_tests {
         let vx = ((opcode & 0x0F00) >> 8) as u8;
 
         chip8.cpu.set_vreg_value(vx, 80);
-        chip8.exec_instructions(opcode);
+        chip8.exec_instructions(opcode).unwrap();
 
         assert_eq!(chip8.cpu.get_vreg_value(0xF), 0, "0xF should be 0");
         assert_eq!(chip8.cpu.get_vreg_value(vx), 80 << 1)
@@ -888,11 +1403,11 @@ mod chip8_tests {
         chip8.cpu.set_vreg_value(vx, 10);
         chip8.cpu.set_vreg_value(vy, 10);
 
-        chip8.exec_instructions(opcode);
+        chip8.exec_instructions(opcode).unwrap();
         assert_eq!(chip8.cpu.get_pc(), 0x200, "Program counter should be zero");
 
         chip8.cpu.set_vreg_value(vy, 12);
-        chip8.exec_instructions(opcode);
+        chip8.exec_instructions(opcode).unwrap();
 
         assert_eq!(chip8.cpu.get_pc(), 0x200 + 2, "Program counter should be 2");
     }
@@ -902,7 +1417,7 @@ mod chip8_tests {
         let mut chip8 = Chip8::init();
         let opcode: u16 = 0xAB20;
 
-        chip8.exec_instructions(opcode);
+        chip8.exec_instructions(opcode).unwrap();
         assert_eq!(chip8.cpu.get_i_reg_value(), opcode & 0x0FFF);
     }
 
@@ -913,7 +1428,7 @@ mod chip8_tests {
 
         chip8.cpu.set_vreg_value(0, 10);
         let val = 10 + (opcode & 0x0FFF);
-        chip8.exec_instructions(opcode);
+        chip8.exec_instructions(opcode).unwrap();
 
         assert_eq!(chip8.cpu.get_pc(), val);
     }
@@ -928,13 +1443,9 @@ mod chip8_tests {
         chip8.bus.handle_key_press(2, true);
 
         let pc = chip8.cpu.get_pc();
-        chip8.exec_instructions(opcode);
+        chip8.exec_instructions(opcode).unwrap();
 
-        assert_eq!(
-            chip8.bus.is_key_pressed(2),
-            true,
-            "key pressed must be true"
-        );
+        assert!(chip8.bus.is_key_pressed(2), "key pressed must be true");
 
         assert_eq!(
             chip8.cpu.get_pc(),
@@ -944,7 +1455,7 @@ mod chip8_tests {
 
         // testing for false condition
         chip8.cpu.set_vreg_value(vx, 3);
-        chip8.exec_instructions(opcode);
+        chip8.exec_instructions(opcode).unwrap();
         assert_eq!(chip8.cpu.get_pc(), pc + 2, "PC must stay the same");
     }
 
@@ -956,13 +1467,9 @@ mod chip8_tests {
         let vx = ((opcode & 0x0F00) >> 8) as u8;
         chip8.cpu.set_vreg_value(vx, 2);
         let pc = chip8.cpu.get_pc();
-        chip8.exec_instructions(opcode);
+        chip8.exec_instructions(opcode).unwrap();
 
-        assert_eq!(
-            chip8.bus.is_key_pressed(2),
-            false,
-            "key pressed must be true"
-        );
+        assert!(!chip8.bus.is_key_pressed(2), "key pressed must be false");
 
         assert_eq!(
             chip8.cpu.get_pc(),
@@ -971,7 +1478,795 @@ mod chip8_tests {
         );
 
         chip8.bus.handle_key_press(2, true);
-        chip8.exec_instructions(opcode);
+        chip8.exec_instructions(opcode).unwrap();
         assert_eq!(chip8.cpu.get_pc(), pc + 2, "PC must stay the same");
     }
+
+    #[test]
+    fn test_unknown_opcode() {
+        let mut chip8 = Chip8::init();
+        chip8.load_rom(vec![0xE0, 0x00]).unwrap();
+
+        let result = chip8.run();
+        assert!(matches!(
+            result,
+            Err(Chip8Error::InvalidOpcode {
+                opcode: 0xE000,
+                pc: 0x200
+            })
+        ));
+    }
+
+    #[test]
+    fn test_stack_overflow() {
+        let mut chip8 = Chip8::init();
+        // calls itself forever
+        chip8.load_rom(vec![0x22, 0x00]).unwrap();
+
+        for _ in 0..16 {
+            chip8.run().unwrap();
+        }
+        assert_eq!(chip8.cpu.get_sp(), 16);
+
+        let result = chip8.run();
+        assert!(matches!(
+            result,
+            Err(Chip8Error::StackOverflow { pc: 0x200 })
+        ));
+    }
+
+    #[test]
+    fn test_stack_underflow() {
+        let mut chip8 = Chip8::init();
+        chip8.load_rom(vec![0x00, 0xEE]).unwrap();
+
+        let result = chip8.run();
+        assert!(matches!(
+            result,
+            Err(Chip8Error::StackUnderflow { pc: 0x200 })
+        ));
+    }
+
+    #[test]
+    fn test_memory_fault() {
+        let mut chip8 = Chip8::init();
+        chip8.cpu.set_i_reg_value(0xFFF);
+
+        // Fx55 with x = 1 writes to I + 1, just past the end of RAM
+        let result = chip8.exec_instructions(0xF155);
+        assert!(matches!(
+            result,
+            Err(Chip8Error::MemoryFault { addr: 0x1000 })
+        ));
+    }
+
+    #[test]
+    fn test_rom_too_large() {
+        let mut chip8 = Chip8::init();
+
+        let result = chip8.load_rom(vec![0; 0x1000]);
+        assert!(matches!(
+            result,
+            Err(Chip8Error::RomTooLarge { max: 0xE00, .. })
+        ));
+    }
+
+    #[test]
+    fn test_8xy6_shift_uses_vy() {
+        let mut chip8 = Chip8::with_quirks(Quirks {
+            shift_uses_vy: true,
+            ..Quirks::default()
+        });
+
+        chip8.cpu.set_vreg_value(1, 0);
+        chip8.cpu.set_vreg_value(2, 5);
+        chip8.exec_instructions(0x8126).unwrap();
+
+        assert_eq!(chip8.cpu.get_vreg_value(1), 5 >> 1);
+        assert_eq!(chip8.cpu.get_vreg_value(0xF), 1, "Vf should be 1");
+    }
+
+    #[test]
+    fn test_8xy6_flag_wins_over_vf() {
+        let mut chip8 = Chip8::init();
+
+        chip8.cpu.set_vreg_value(0xF, 2);
+        chip8.exec_instructions(0x8F06).unwrap();
+
+        assert_eq!(chip8.cpu.get_vreg_value(0xF), 0, "Vf should hold the flag");
+    }
+
+    #[test]
+    fn test_8xy1_vf_reset() {
+        let mut chip8 = Chip8::with_quirks(Quirks {
+            vf_reset: true,
+            ..Quirks::default()
+        });
+
+        chip8.cpu.set_vreg_value(0xF, 1);
+        chip8.exec_instructions(0x8371).unwrap();
+
+        assert_eq!(chip8.cpu.get_vreg_value(0xF), 0, "Vf should be reset");
+    }
+
+    #[test]
+    fn test_fx55_increments_i() {
+        let mut chip8 = Chip8::with_quirks(Quirks {
+            load_store_increments_i: true,
+            ..Quirks::default()
+        });
+
+        chip8.cpu.set_i_reg_value(0x300);
+        chip8.exec_instructions(0xF355).unwrap();
+        assert_eq!(chip8.cpu.get_i_reg_value(), 0x304);
+
+        chip8.exec_instructions(0xF165).unwrap();
+        assert_eq!(chip8.cpu.get_i_reg_value(), 0x306);
+    }
+
+    #[test]
+    fn test_bxnn_jump_uses_vx() {
+        let mut chip8 = Chip8::with_quirks(Quirks {
+            jump_uses_vx: true,
+            ..Quirks::default()
+        });
+
+        chip8.cpu.set_vreg_value(0, 10);
+        chip8.cpu.set_vreg_value(3, 20);
+        chip8.exec_instructions(0xB300).unwrap();
+
+        assert_eq!(chip8.cpu.get_pc(), 0x300 + 20);
+    }
+
+    #[test]
+    fn test_dxyn_collision() {
+        let mut chip8 = Chip8::init();
+
+        // font sprite for 0, drawn twice at the same place
+        chip8.exec_instructions(0xF029).unwrap();
+        chip8.exec_instructions(0xD005).unwrap();
+        assert_eq!(
+            chip8.cpu.get_vreg_value(0xF),
+            0,
+            "no collision on empty screen"
+        );
+
+        chip8.exec_instructions(0xD005).unwrap();
+        assert_eq!(
+            chip8.cpu.get_vreg_value(0xF),
+            1,
+            "second draw should collide"
+        );
+        assert!(chip8.get_video_buffer().iter().all(|&pixel| pixel == 0));
+    }
+
+    #[test]
+    fn test_dxyn_clip_and_wrap() {
+        let mut wrapping = Chip8::init();
+        let mut clipping = Chip8::with_quirks(Quirks {
+            clip_sprites: true,
+            ..Quirks::default()
+        });
+
+        for chip8 in [&mut wrapping, &mut clipping] {
+            // 0xF0 top row of the 0 sprite at x = 62, y = 31
+            chip8.cpu.set_vreg_value(0, 62);
+            chip8.cpu.set_vreg_value(1, 31);
+            chip8.exec_instructions(0xF229).unwrap();
+            chip8.exec_instructions(0xD012).unwrap();
+        }
+
+        let last_row = 31 * 64;
+        assert_eq!(
+            wrapping.get_video_buffer()[last_row],
+            1,
+            "should wrap to x = 0"
+        );
+        assert_eq!(wrapping.get_video_buffer()[1], 1, "should wrap to y = 0");
+        assert_eq!(
+            clipping.get_video_buffer()[last_row],
+            0,
+            "should be clipped"
+        );
+        assert_eq!(clipping.get_video_buffer()[1], 0, "should be clipped");
+        assert_eq!(clipping.get_video_buffer()[last_row + 63], 1);
+    }
+
+    #[test]
+    fn test_dxyn_display_wait() {
+        let mut chip8 = Chip8::with_quirks(Quirks {
+            display_wait: true,
+            ..Quirks::default()
+        });
+
+        // two draws in a row, the second one has to wait for the next frame
+        chip8.load_rom(vec![0xD0, 0x01, 0xD0, 0x01]).unwrap();
+        chip8.run().unwrap();
+        chip8.run().unwrap();
+        assert_eq!(chip8.cpu.get_pc(), 0x202);
+
+        chip8.update_timer();
+        chip8.run().unwrap();
+        assert_eq!(chip8.cpu.get_pc(), 0x204);
+    }
+
+    #[test]
+    fn test_platform_presets() {
+        assert_eq!("schip".parse::<Platform>(), Ok(Platform::SuperChip11));
+        assert!("gameboy".parse::<Platform>().is_err());
+
+        let mut vip = Chip8::with_platform(Platform::CosmacVip);
+        assert!(vip.config.quirks.vf_reset);
+        assert!(vip.load_rom(vec![0; 0x1000]).is_err());
+
+        let mut xochip = Chip8::with_platform(Platform::XoChip);
+        assert!(xochip.load_rom(vec![0; 0x1000]).is_ok());
+    }
+
+    #[test]
+    fn test_lores_scaled_on_large_display() {
+        let mut chip8 = Chip8::with_platform(Platform::SuperChip11);
+
+        // one lit pixel at (1, 1) from the top row of the 1 sprite (0x20)
+        chip8.cpu.set_vreg_value(0, 1);
+        chip8.cpu.set_vreg_value(1, 1);
+        chip8.cpu.set_vreg_value(2, 1);
+        chip8.exec_instructions(0xF229).unwrap();
+        chip8.exec_instructions(0xD011).unwrap();
+
+        let buffer = chip8.get_video_buffer();
+        let lit: Vec<usize> = (0..buffer.len()).filter(|&i| buffer[i] == 1).collect();
+
+        // x = 3 becomes the 2x2 block at (6, 2) on the 128x64 screen
+        assert_eq!(
+            lit,
+            vec![2 * 128 + 6, 2 * 128 + 7, 3 * 128 + 6, 3 * 128 + 7]
+        );
+    }
+
+    fn lit_pixels(chip8: &Chip8) -> Vec<usize> {
+        let buffer = chip8.get_video_buffer();
+        (0..buffer.len()).filter(|&i| buffer[i] != 0).collect()
+    }
+
+    #[test]
+    fn test_superchip_opcodes_need_platform() {
+        let mut chip8 = Chip8::init();
+
+        for opcode in [0x00FF, 0x00C1, 0x00FD, 0xF030, 0xF075] {
+            let result = chip8.exec_instructions(opcode);
+            assert!(matches!(result, Err(Chip8Error::InvalidOpcode { .. })));
+        }
+    }
+
+    #[test]
+    fn test_00ff_hires() {
+        let mut chip8 = Chip8::with_platform(Platform::SuperChip11);
+        chip8.exec_instructions(0x00FF).unwrap();
+
+        // top row of the 1 sprite (0x20) at (0, 0) lights a single pixel
+        chip8.cpu.set_vreg_value(2, 1);
+        chip8.exec_instructions(0xF229).unwrap();
+        chip8.exec_instructions(0xD001).unwrap();
+        assert_eq!(lit_pixels(&chip8), vec![2]);
+
+        // going back to low resolution clears the screen
+        chip8.exec_instructions(0x00FE).unwrap();
+        assert!(lit_pixels(&chip8).is_empty());
+    }
+
+    #[test]
+    fn test_superchip_scroll() {
+        let mut chip8 = Chip8::with_platform(Platform::SuperChip11);
+        chip8.exec_instructions(0x00FF).unwrap();
+        chip8.bus.display_write_pixel(10, 1);
+
+        chip8.exec_instructions(0x00C3).unwrap();
+        assert_eq!(lit_pixels(&chip8), vec![3 * 128 + 10]);
+
+        chip8.exec_instructions(0x00FB).unwrap();
+        assert_eq!(lit_pixels(&chip8), vec![3 * 128 + 14]);
+
+        chip8.exec_instructions(0x00FC).unwrap();
+        chip8.exec_instructions(0x00FC).unwrap();
+        assert_eq!(lit_pixels(&chip8), vec![3 * 128 + 6]);
+    }
+
+    #[test]
+    fn test_dxy0_big_sprite() {
+        let mut chip8 = Chip8::with_platform(Platform::SuperChip11);
+        chip8.exec_instructions(0x00FF).unwrap();
+
+        chip8.cpu.set_i_reg_value(0x300);
+        for i in 0..32 {
+            chip8.bus.ram_write_byte(0x300 + i, 0xFF).unwrap();
+        }
+        chip8.exec_instructions(0xD000).unwrap();
+
+        let lit = lit_pixels(&chip8);
+        assert_eq!(lit.len(), 16 * 16);
+        assert_eq!(*lit.last().unwrap(), 15 * 128 + 15);
+    }
+
+    #[test]
+    fn test_fx30_big_font() {
+        let mut chip8 = Chip8::with_platform(Platform::SuperChip11);
+
+        chip8.cpu.set_vreg_value(4, 3);
+        chip8.exec_instructions(0xF430).unwrap();
+
+        assert_eq!(
+            chip8.cpu.get_i_reg_value(),
+            crate::ram::BIG_FONTSET_START_ADDRESS + 30
+        );
+    }
+
+    #[test]
+    fn test_fx75_fx85_rpl_flags() {
+        let mut chip8 = Chip8::with_platform(Platform::SuperChip11);
+
+        for i in 0..4 {
+            chip8.cpu.set_vreg_value(i, i + 10);
+        }
+        chip8.exec_instructions(0xF375).unwrap();
+
+        for i in 0..4 {
+            chip8.cpu.set_vreg_value(i, 0);
+        }
+        chip8.exec_instructions(0xF285).unwrap();
+
+        assert_eq!(chip8.cpu.get_vreg_value(2), 12);
+        assert_eq!(chip8.cpu.get_vreg_value(3), 0, "V3 was not restored");
+    }
+
+    #[test]
+    fn test_00fd_exit() {
+        let mut chip8 = Chip8::with_platform(Platform::SuperChip11);
+
+        chip8.exec_instructions(0x00FD).unwrap();
+        assert!(chip8.state == EmulatorState::Quit);
+
+        // the invalid opcode after the exit never runs
+        let mut chip8 = Chip8::with_platform(Platform::SuperChip11);
+        chip8.load_rom(vec![0x00, 0xFD, 0xFF, 0xFF]).unwrap();
+        chip8.run_frame().unwrap();
+        chip8.run_frame().unwrap();
+        assert!(chip8.state == EmulatorState::Quit);
+        assert_eq!(chip8.cpu.get_pc(), 0x202);
+    }
+
+    #[test]
+    fn test_xochip_opcodes_need_platform() {
+        let mut chip8 = Chip8::with_platform(Platform::SuperChip11);
+
+        assert!(chip8.exec_instructions(0xF000).is_err());
+        assert!(chip8.exec_instructions(0x5012).is_err());
+        assert!(chip8.exec_instructions(0xF201).is_err());
+    }
+
+    #[test]
+    fn test_f000_long_i() {
+        let mut chip8 = Chip8::with_platform(Platform::XoChip);
+        chip8.bus.ram_write_byte(0x200, 0xAB).unwrap();
+        chip8.bus.ram_write_byte(0x201, 0xCD).unwrap();
+
+        chip8.exec_instructions(0xF000).unwrap();
+
+        assert_eq!(chip8.cpu.get_i_reg_value(), 0xABCD);
+        assert_eq!(chip8.cpu.get_pc(), 0x202);
+    }
+
+    #[test]
+    fn test_skip_over_f000() {
+        let mut chip8 = Chip8::with_platform(Platform::XoChip);
+        chip8.bus.ram_write_byte(0x200, 0xF0).unwrap();
+        chip8.bus.ram_write_byte(0x201, 0x00).unwrap();
+
+        chip8.exec_instructions(0x3000).unwrap();
+        assert_eq!(chip8.cpu.get_pc(), 0x204);
+    }
+
+    #[test]
+    fn test_pc_wraps_at_end_of_memory() {
+        let mut chip8 = Chip8::with_platform(Platform::XoChip);
+        // F000 0000 at the top of memory, then SE V0, 0x00 over F000 0000
+        for (addr, byte) in [
+            (0xFFFC, 0xF0),
+            (0xFFFD, 0x00),
+            (0x0000, 0x30),
+            (0x0002, 0xF0),
+        ] {
+            chip8.bus.ram_write_byte(addr, byte).unwrap();
+        }
+        chip8.cpu.update_pc(0xFFFC);
+
+        chip8.step().unwrap();
+        assert_eq!(chip8.cpu.get_pc(), 0x0000);
+
+        chip8.step().unwrap();
+        assert_eq!(chip8.cpu.get_pc(), 0x0006);
+    }
+
+    #[test]
+    fn test_5xy2_5xy3_register_range() {
+        let mut chip8 = Chip8::with_platform(Platform::XoChip);
+        chip8.cpu.set_i_reg_value(0x300);
+        for i in 0..4 {
+            chip8.cpu.set_vreg_value(i, i + 1);
+        }
+
+        chip8.exec_instructions(0x5132).unwrap();
+        assert_eq!(chip8.bus.ram_read_byte(0x300).unwrap(), 2);
+        assert_eq!(chip8.bus.ram_read_byte(0x302).unwrap(), 4);
+        assert_eq!(chip8.cpu.get_i_reg_value(), 0x300);
+
+        // reversed range stores V3 first
+        chip8.exec_instructions(0x5312).unwrap();
+        assert_eq!(chip8.bus.ram_read_byte(0x300).unwrap(), 4);
+        assert_eq!(chip8.bus.ram_read_byte(0x302).unwrap(), 2);
+
+        chip8.exec_instructions(0x5A83).unwrap();
+        assert_eq!(chip8.cpu.get_vreg_value(0xA), 4);
+        assert_eq!(chip8.cpu.get_vreg_value(0x8), 2);
+    }
+
+    #[test]
+    fn test_dxyn_bitplanes() {
+        let mut chip8 = Chip8::with_platform(Platform::XoChip);
+        chip8.cpu.set_i_reg_value(0x300);
+        chip8.bus.ram_write_byte(0x300, 0x80).unwrap();
+        chip8.bus.ram_write_byte(0x301, 0xC0).unwrap();
+
+        // plane 2 only
+        chip8.exec_instructions(0xF201).unwrap();
+        chip8.exec_instructions(0xD001).unwrap();
+        assert_eq!(chip8.get_video_buffer()[0], display::PLANE_2);
+
+        // both planes read one sprite each, plane 2 collides
+        chip8.exec_instructions(0xF301).unwrap();
+        chip8.exec_instructions(0xD001).unwrap();
+        assert_eq!(chip8.get_video_buffer()[0], display::PLANE_1);
+        assert_eq!(chip8.get_video_buffer()[2], display::PLANE_2);
+        assert_eq!(chip8.cpu.get_vreg_value(0xF), 1);
+    }
+
+    #[test]
+    fn test_plane_aware_clear_and_scroll() {
+        let mut chip8 = Chip8::with_platform(Platform::XoChip);
+        chip8.cpu.set_i_reg_value(0x300);
+        chip8.bus.ram_write_byte(0x300, 0x80).unwrap();
+        chip8.bus.ram_write_byte(0x301, 0x80).unwrap();
+        chip8.exec_instructions(0x00FF).unwrap();
+        chip8.exec_instructions(0xF301).unwrap();
+        chip8.exec_instructions(0xD001).unwrap();
+
+        // only plane 1 moves
+        chip8.exec_instructions(0xF101).unwrap();
+        chip8.exec_instructions(0x00C1).unwrap();
+        assert_eq!(chip8.get_video_buffer()[0], display::PLANE_2);
+        assert_eq!(chip8.get_video_buffer()[128], display::PLANE_1);
+
+        chip8.exec_instructions(0x00D1).unwrap();
+        assert_eq!(
+            chip8.get_video_buffer()[0],
+            display::PLANE_1 | display::PLANE_2
+        );
+
+        chip8.exec_instructions(0x00E0).unwrap();
+        assert_eq!(lit_pixels(&chip8), vec![0]);
+        assert_eq!(chip8.get_video_buffer()[0], display::PLANE_2);
+    }
+
+    #[test]
+    fn test_f002_fx3a_audio() {
+        let mut chip8 = Chip8::with_platform(Platform::XoChip);
+        chip8.cpu.set_i_reg_value(0x300);
+        for i in 0..16 {
+            chip8.bus.ram_write_byte(0x300 + i, i as u8).unwrap();
+        }
+        chip8.cpu.set_vreg_value(2, 112);
+
+        chip8.exec_instructions(0xF002).unwrap();
+        chip8.exec_instructions(0xF23A).unwrap();
+
+        let audio = chip8.bus.get_audio();
+        assert_eq!(audio.get_pattern().unwrap()[15], 15);
+        assert_eq!(audio.get_pitch(), 112);
+        assert_eq!(audio.playback_rate(), 8000.0);
+    }
+
+    #[test]
+    fn test_sound_timer_plays_every_frame() {
+        let mut chip8 = Chip8::init();
+        // V0 = 1, sound timer = V0, loop forever
+        chip8
+            .load_rom(vec![0x60, 0x01, 0xF0, 0x18, 0x12, 0x04])
+            .unwrap();
+
+        chip8.run_frame().unwrap();
+        assert_eq!(chip8.cpu.get_sound_timer(), 0);
+        assert!(chip8.is_sound_playing());
+        let snapshot = chip8.snapshot();
+
+        chip8.run_frame().unwrap();
+        assert!(!chip8.is_sound_playing());
+
+        // the timer already reads 0, the buzzer comes back from the state
+        chip8.restore(&snapshot).unwrap();
+        assert!(chip8.is_sound_playing());
+    }
+
+    #[test]
+    fn test_vip_timing_cycle_budget() {
+        let mut chip8 = Chip8::with_platform(Platform::CosmacVip);
+        chip8.config.timing = Timing::CosmacVip;
+        // V0 = V0 + 1 over and over
+        chip8.load_rom([0x70, 0x01].repeat(200)).unwrap();
+
+        let cost = timing::vip_cycles(0x7001, &chip8.cpu);
+        let budget = timing::VIP_INTERPRETER_CYCLES;
+
+        // the instruction running at the interrupt finishes, the overrun is paid back
+        chip8.run_frame().unwrap();
+        assert_eq!(
+            u32::from(chip8.cpu.get_vreg_value(0)),
+            budget.div_ceil(cost)
+        );
+
+        chip8.run_frame().unwrap();
+        assert_eq!(
+            u32::from(chip8.cpu.get_vreg_value(0)),
+            (2 * budget).div_ceil(cost)
+        );
+    }
+
+    #[test]
+    fn test_vip_timing_waits_for_vblank() {
+        let mut chip8 = Chip8::init();
+        chip8.config.timing = Timing::CosmacVip;
+        chip8.load_rom(vec![0xD0, 0x01, 0xD0, 0x01]).unwrap();
+        chip8.bus.ram_write_byte(0, 0x80).unwrap();
+
+        chip8.run_frame().unwrap();
+        assert_eq!(chip8.cpu.get_pc(), 0x202);
+
+        chip8.run_frame().unwrap();
+        assert_eq!(chip8.cpu.get_pc(), 0x204);
+    }
+
+    #[test]
+    fn test_step_through_frame() {
+        let mut chip8 = Chip8::init();
+        chip8.config.instructions_per_second = 120;
+        chip8.load_rom([0x70, 0x01].repeat(10)).unwrap();
+        chip8.cpu.set_delay_timer(5);
+
+        assert_eq!(chip8.next_instruction(), Some(0x200));
+        assert_eq!(
+            chip8.step().unwrap(),
+            Step::Instruction {
+                pc: 0x200,
+                opcode: 0x7001
+            }
+        );
+        assert!(chip8.in_frame());
+        assert!(matches!(
+            chip8.step().unwrap(),
+            Step::Instruction { pc: 0x202, .. }
+        ));
+
+        // two instructions per frame, then the timers tick
+        assert_eq!(chip8.next_instruction(), None);
+        assert_eq!(chip8.step().unwrap(), Step::FrameEnd);
+        assert!(!chip8.in_frame());
+        assert_eq!(chip8.cpu.get_delay_timer(), 4);
+        assert_eq!(chip8.next_instruction(), Some(0x204));
+    }
+
+    #[test]
+    fn test_step_matches_run_frame() {
+        let rom = vec![0xC0, 0xFF, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x00];
+
+        for timing in Timing::ALL {
+            let mut config = Platform::CosmacVip.config();
+            config.timing = timing;
+            config.seed = Some(1);
+
+            let mut framed = Chip8::with_config(config);
+            let mut stepped = Chip8::with_config(config);
+            framed.load_rom(rom.clone()).unwrap();
+            stepped.load_rom(rom.clone()).unwrap();
+
+            for _ in 0..5 {
+                framed.run_frame().unwrap();
+                while stepped.step().unwrap() != Step::FrameEnd {}
+            }
+            assert_eq!(framed.save_state(), stepped.save_state());
+
+            // a frame left half done by `step` is finished by `run_frame`
+            stepped.step().unwrap();
+            let saved = stepped.save_state();
+            stepped.run_frame().unwrap();
+            framed.run_frame().unwrap();
+            assert_eq!(framed.save_state(), stepped.save_state());
+
+            framed.load_state(&saved).unwrap();
+            assert!(framed.in_frame());
+        }
+    }
+
+    #[test]
+    fn test_vip_cycles_depend_on_operands() {
+        let mut chip8 = Chip8::init();
+
+        chip8.cpu.set_vreg_value(0, 8);
+        let aligned = timing::vip_cycles(0xD005, &chip8.cpu);
+        chip8.cpu.set_vreg_value(0, 3);
+        let shifted = timing::vip_cycles(0xD005, &chip8.cpu);
+        assert!(shifted > aligned);
+
+        chip8.cpu.set_vreg_value(0, 0);
+        let short = timing::vip_cycles(0xF033, &chip8.cpu);
+        chip8.cpu.set_vreg_value(0, 199);
+        assert!(timing::vip_cycles(0xF033, &chip8.cpu) > short);
+    }
+
+    #[test]
+    fn test_save_state_roundtrip() {
+        let mut chip8 = Chip8::with_platform(Platform::XoChip);
+        // V0 = random, draw the V0 digit at (V0, V0), jump back
+        chip8
+            .load_rom(vec![0xC0, 0xFF, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x00])
+            .unwrap();
+        chip8.cpu.set_sound_timer(30);
+        chip8.bus.handle_key_press(3, true);
+        for _ in 0..3 {
+            chip8.run_frame().unwrap();
+        }
+
+        let saved = chip8.save_state();
+        for _ in 0..3 {
+            chip8.run_frame().unwrap();
+        }
+        let expected = chip8.save_state();
+
+        chip8.load_state(&saved).unwrap();
+        assert_eq!(chip8.save_state(), saved);
+
+        // same random numbers come out again
+        for _ in 0..3 {
+            chip8.run_frame().unwrap();
+        }
+        assert_eq!(chip8.save_state(), expected);
+    }
+
+    #[test]
+    fn test_restore_keeps_watchpoints() {
+        let mut chip8 = Chip8::init();
+        let watchpoint = Watchpoint::init(WatchKind::Write, 0x300, 1);
+        chip8.bus.add_watchpoint(watchpoint);
+
+        let snapshot = chip8.snapshot();
+        chip8.restore(&snapshot).unwrap();
+        assert_eq!(chip8.bus.watchpoints(), &[watchpoint]);
+
+        chip8.cpu.set_i_reg_value(0x300);
+        chip8.exec_instructions(0xF055).unwrap();
+        assert!(chip8.bus.take_watch_hit().is_some());
+    }
+
+    #[test]
+    fn test_save_state_rejects_other_platform() {
+        let vip = Chip8::with_platform(Platform::CosmacVip);
+        let mut chip48 = Chip8::with_platform(Platform::Chip48);
+
+        let result = chip48.load_state(&vip.save_state());
+        assert!(matches!(result, Err(Chip8Error::SaveStatePlatform)));
+
+        // the same saved random state means something else to the other model
+        let mut page_sum_random = Chip8::with_config(PlatformConfig {
+            random_model: RandomModel::PageSum,
+            ..PlatformConfig::default()
+        });
+        let result = page_sum_random.load_state(&Chip8::init().save_state());
+        assert!(matches!(result, Err(Chip8Error::SaveStatePlatform)));
+    }
+
+    #[test]
+    fn test_save_state_rejects_bad_data() {
+        let mut chip8 = Chip8::init();
+        chip8.cpu.set_vreg_value(1, 42);
+        let mut saved = chip8.save_state();
+
+        let mut other = Chip8::init();
+        let result = other.load_state(&saved[..saved.len() - 1]);
+        assert!(matches!(result, Err(Chip8Error::InvalidSaveState(_))));
+        assert!(matches!(
+            other.load_state(b"not a state"),
+            Err(Chip8Error::InvalidSaveState(_))
+        ));
+        assert_eq!(other.cpu.get_vreg_value(1), 0);
+
+        saved[8] = saved[8].wrapping_add(1);
+        let result = other.load_state(&saved);
+        assert!(matches!(
+            result,
+            Err(Chip8Error::SaveStateVersion {
+                expected: savestate::VERSION,
+                ..
+            })
+        ));
+    }
+
+    fn random_bytes(config: PlatformConfig) -> Vec<u8> {
+        let mut chip8 = Chip8::with_config(config);
+        (0..16)
+            .map(|_| {
+                chip8.exec_instructions(0xC0FF).unwrap();
+                chip8.cpu.get_vreg_value(0)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_cxkk_seed() {
+        let seeded = |seed| PlatformConfig {
+            seed: Some(seed),
+            ..PlatformConfig::default()
+        };
+
+        assert_eq!(random_bytes(seeded(7)), random_bytes(seeded(7)));
+        assert_ne!(random_bytes(seeded(7)), random_bytes(seeded(8)));
+    }
+
+    #[test]
+    fn test_cxkk_page_sum_model() {
+        let config = PlatformConfig {
+            random_model: RandomModel::PageSum,
+            seed: Some(0),
+            ..PlatformConfig::default()
+        };
+
+        // running sum of the first memory page, starting at address 1
+        let mut chip8 = Chip8::with_config(config);
+        let mut expected = 0u8;
+        for addr in 1..=0x60 {
+            expected = expected.wrapping_add(chip8.bus.ram_read_byte(addr).unwrap());
+            chip8.exec_instructions(0xC0FF).unwrap();
+            assert_eq!(chip8.cpu.get_vreg_value(0), expected);
+        }
+    }
+
+    #[test]
+    fn test_instruction_rate_is_exact() {
+        let mut chip8 = Chip8::init();
+        chip8.config.instructions_per_second = 1000;
+        chip8.cpu.set_vreg_value(0, 1);
+        chip8.cpu.set_delay_timer(100);
+        // every instruction is I = I + V0
+        chip8.load_rom([0xF0, 0x1E].repeat(1000)).unwrap();
+
+        for _ in 0..60 {
+            chip8.run_frame().unwrap();
+        }
+
+        assert_eq!(chip8.cpu.get_i_reg_value(), 1000);
+        assert_eq!(chip8.cpu.get_delay_timer(), 40);
+    }
+
+    #[test]
+    fn test_display_text_and_hash() {
+        let mut chip8 = Chip8::with_platform(Platform::XoChip);
+        let blank = chip8.bus.display_hash();
+
+        chip8.bus.display_write_pixel(0, display::PLANE_1);
+        chip8
+            .bus
+            .display_write_pixel(2, display::PLANE_1 | display::PLANE_2);
+
+        let text = chip8.bus.display_to_text();
+        assert!(text.starts_with("#.*."));
+        assert_eq!(text.lines().count(), 64);
+        assert_ne!(chip8.bus.display_hash(), blank);
+    }
 }
@@ -1,4 +1,8 @@
+use crate::error::Chip8Error;
+use crate::savestate::{StateReader, StateWriter};
+
 pub const EXECUTION_INDEX: u16 = 0x200;
+pub const STACK_SIZE: usize = 16;
 
 pub struct CPU {
     v_regs: [u8; 16],
@@ -7,7 +11,9 @@ pub struct CPU {
     sound_reg: u8,
     program_counter: u16,
     stack_pointer: u8,
-    stack: [u16; 16],
+    stack: [u16; STACK_SIZE],
+    // SUPER-CHIP RPL user flags, kept across Fx75 / Fx85
+    rpl_flags: [u8; 16],
 }
 
 impl CPU {
@@ -18,12 +24,13 @@ impl CPU {
             delay_reg: 0,
             sound_reg: 0,
             program_counter: EXECUTION_INDEX,
-            stack: [0; 16],
+            stack: [0; STACK_SIZE],
             stack_pointer: 0,
+            rpl_flags: [0; 16],
         }
     }
 
-    ///stacks
+    ///stacks, `stack_pointer` counts the entries in slots 0 to 15
     pub fn get_stack_value(&self, stack_pointer: u8) -> u16 {
         self.stack[stack_pointer as usize]
     }
@@ -32,16 +39,39 @@ impl CPU {
         self.stack[stack_pointer as usize] = value;
     }
 
-    pub fn decrease_sp(&mut self) {
-        if self.stack_pointer > 0 {
-            self.stack_pointer -= 1;
+    // 00EE and 2nnn run with the program counter already past the opcode
+    pub fn decrease_sp(&mut self) -> Result<(), Chip8Error> {
+        if self.stack_pointer == 0 {
+            return Err(Chip8Error::StackUnderflow {
+                pc: self.program_counter.wrapping_sub(2),
+            });
+        }
+
+        self.stack_pointer -= 1;
+        Ok(())
+    }
+
+    pub fn increase_sp(&mut self) -> Result<(), Chip8Error> {
+        if self.stack_pointer as usize >= STACK_SIZE {
+            return Err(Chip8Error::StackOverflow {
+                pc: self.program_counter.wrapping_sub(2),
+            });
         }
+
+        self.stack_pointer += 1;
+        Ok(())
     }
 
-    pub fn increase_sp(&mut self) {
-        if self.stack_pointer < 255 {
-            self.stack_pointer += 1;
+    /// Keeps the first `value` stack entries, the slots above them are unused
+    pub fn set_sp(&mut self, value: u8) -> Result<(), Chip8Error> {
+        if value as usize > STACK_SIZE {
+            return Err(Chip8Error::StackOverflow {
+                pc: self.program_counter,
+            });
         }
+
+        self.stack_pointer = value;
+        Ok(())
     }
 
     /// program counter
@@ -49,13 +79,13 @@ impl CPU {
         self.program_counter = val;
     }
 
-    // increment program counter by two
+    // increment program counter by two, XO-CHIP wraps around at 0xFFFF
     pub fn increment_pc(&mut self) {
-        self.program_counter += 2;
+        self.program_counter = self.program_counter.wrapping_add(2);
     }
 
     pub fn decrease_pc(&mut self) {
-        self.program_counter -= 2;
+        self.program_counter = self.program_counter.wrapping_sub(2);
     }
 
     /// V registers
@@ -67,6 +97,15 @@ impl CPU {
         self.v_regs[index as usize]
     }
 
+    /// RPL user flags
+    pub fn set_rpl_flag(&mut self, index: u8, val: u8) {
+        self.rpl_flags[index as usize] = val;
+    }
+
+    pub fn get_rpl_flag(&self, index: u8) -> u8 {
+        self.rpl_flags[index as usize]
+    }
+
     /// I register
     pub fn set_i_reg_value(&mut self, value: u16) {
         self.i_reg = value;
@@ -89,6 +128,41 @@ impl CPU {
     }
 }
 
+/// Save states
+impl CPU {
+    pub fn save_state(&self, out: &mut StateWriter) {
+        out.put_bytes(&self.v_regs);
+        out.put_u16(self.i_reg);
+        out.put_u8(self.delay_reg);
+        out.put_u8(self.sound_reg);
+        out.put_u16(self.program_counter);
+        out.put_u8(self.stack_pointer);
+        for value in self.stack {
+            out.put_u16(value);
+        }
+        out.put_bytes(&self.rpl_flags);
+    }
+
+    pub fn load_state(&mut self, input: &mut StateReader) -> Result<(), Chip8Error> {
+        self.v_regs.copy_from_slice(input.get_bytes(16)?);
+        self.i_reg = input.get_u16()?;
+        self.delay_reg = input.get_u8()?;
+        self.sound_reg = input.get_u8()?;
+        self.program_counter = input.get_u16()?;
+        self.stack_pointer = input.get_u8()?;
+        for value in self.stack.iter_mut() {
+            *value = input.get_u16()?;
+        }
+        self.rpl_flags.copy_from_slice(input.get_bytes(16)?);
+
+        if self.stack_pointer as usize > STACK_SIZE {
+            return Err(Chip8Error::InvalidSaveState("stack pointer out of range"));
+        }
+
+        Ok(())
+    }
+}
+
 /// Access to read only fields
 impl CPU {
     pub fn get_sp(&self) -> u8 {
@@ -0,0 +1,664 @@
+use std::{
+    collections::BTreeSet,
+    fmt::{self, Write},
+    io::{self, BufRead},
+    mem,
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread,
+};
+
+use crate::chip8::{Chip8, EmulatorState, Step};
+use crate::error::Chip8Error;
+use crate::watch::{WatchHit, WatchKind, Watchpoint};
+
+const HELP: &str = "\
+commands, addresses and bytes are hex, counts are decimal:
+  c, continue              run until a breakpoint
+  p, pause                 stop running
+  s, step [n]              run n instructions (1)
+  f, frame [n]             run to the end of the frame, n times (1)
+  b, break [addr]          add a breakpoint, or list them
+  d, delete [addr]         remove a breakpoint, or all of them
+  watch [kind addr [len]]  stop after an instruction reads, writes, accesses
+                           or changes len bytes (1), or list watchpoints
+  unwatch [addr]           remove the watchpoints at addr, or all of them
+  r, regs                  show registers, timers and the stack
+  m, mem <addr> [len]      dump len bytes of memory (64)
+  w, write <addr> <byte>.. write bytes to memory
+  q, quit                  quit the emulator";
+
+// bytes per line of a memory dump
+const DUMP_WIDTH: usize = 16;
+const DEFAULT_DUMP_LENGTH: usize = 64;
+
+/// Command line debugger. Commands come in as text, from stdin or from
+/// `execute`, and it runs the emulator in place of `Chip8::run_frame`,
+/// stopping at breakpoints. Starts paused.
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    paused: bool,
+    // continuing from a breakpoint runs its instruction before checking again
+    skip_breakpoint: bool,
+    // lines read from stdin by a background thread
+    input: Option<Receiver<String>>,
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Debugger::init()
+    }
+}
+
+impl Debugger {
+    /// Debugger without an input, driven through `execute`
+    pub fn init() -> Debugger {
+        Debugger {
+            breakpoints: BTreeSet::new(),
+            paused: true,
+            skip_breakpoint: false,
+            input: None,
+        }
+    }
+
+    /// Debugger reading commands from stdin, one per line
+    pub fn with_stdin() -> Debugger {
+        let (sender, receiver) = mpsc::channel();
+
+        thread::spawn(move || {
+            for line in io::stdin().lock().lines() {
+                let Ok(line) = line else { break };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Debugger {
+            input: Some(receiver),
+            ..Debugger::init()
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Stops running and shows where
+    pub fn pause(&mut self, emulator: &mut Chip8) {
+        self.paused = true;
+        println!("paused, type help for commands\n{}", registers(emulator));
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    /// Runs the rest of the frame unless paused, and returns whether the
+    /// frame ended. Pauses before an instruction with a breakpoint, and
+    /// after one that hit a watchpoint.
+    pub fn run_frame(&mut self, emulator: &mut Chip8) -> Result<bool, Chip8Error> {
+        if self.paused {
+            return Ok(false);
+        }
+
+        let skip = mem::take(&mut self.skip_breakpoint);
+        match run_until(emulator, &self.breakpoints, skip, |step| {
+            step == Step::FrameEnd
+        })? {
+            Some(stop) => {
+                self.paused = true;
+                println!("{}\n{}", stop, registers(emulator));
+                Ok(false)
+            }
+            None => Ok(true),
+        }
+    }
+
+    /// Runs every command typed since the last call, printing the output
+    pub fn poll_commands(&mut self, emulator: &mut Chip8) {
+        while let Some(input) = &self.input {
+            match input.try_recv() {
+                Ok(line) => self.run_command(emulator, &line),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => self.input_closed(),
+            }
+        }
+    }
+
+    /// Blocks until the next command and runs it. Returns false once stdin
+    /// is closed, the debugger then lets the emulator run
+    pub fn wait_command(&mut self, emulator: &mut Chip8) -> bool {
+        let Some(input) = &self.input else {
+            return false;
+        };
+
+        match input.recv() {
+            Ok(line) => {
+                self.run_command(emulator, &line);
+                true
+            }
+            Err(_) => {
+                self.input_closed();
+                false
+            }
+        }
+    }
+
+    /// Runs one command and returns what it prints
+    pub fn execute(&mut self, emulator: &mut Chip8, line: &str) -> String {
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            return String::new();
+        };
+        let args: Vec<&str> = words.collect();
+
+        let result = match command {
+            "h" | "help" | "?" => Ok(HELP.to_string()),
+
+            "c" | "continue" => {
+                self.paused = false;
+                self.skip_breakpoint = true;
+                Ok("running".to_string())
+            }
+
+            "p" | "pause" => {
+                self.paused = true;
+                Ok(registers(emulator))
+            }
+
+            "s" | "step" => count_arg(&args).and_then(|count| {
+                let mut left = count;
+                self.run_for(emulator, |step| {
+                    if let Step::Instruction { .. } = step {
+                        left -= 1;
+                    }
+                    left == 0
+                })
+            }),
+
+            "f" | "frame" => count_arg(&args).and_then(|count| {
+                let mut left = count;
+                self.run_for(emulator, |step| {
+                    if step == Step::FrameEnd {
+                        left -= 1;
+                    }
+                    left == 0
+                })
+            }),
+
+            "b" | "break" => match args.first() {
+                Some(arg) => parse_hex(arg).map(|addr| {
+                    self.add_breakpoint(addr);
+                    format!("breakpoint at {:03X}", addr)
+                }),
+                None => Ok(self.list_breakpoints()),
+            },
+
+            "d" | "delete" => match args.first() {
+                Some(arg) => parse_hex(arg).map(|addr| {
+                    if self.remove_breakpoint(addr) {
+                        format!("removed breakpoint at {:03X}", addr)
+                    } else {
+                        format!("no breakpoint at {:03X}", addr)
+                    }
+                }),
+                None => {
+                    self.breakpoints.clear();
+                    Ok("removed all breakpoints".to_string())
+                }
+            },
+
+            "watch" => match args.as_slice() {
+                [] => Ok(list_watchpoints(emulator)),
+                [kind, addr, rest @ ..] => watch_args(kind, addr, rest).map(|watchpoint| {
+                    emulator.bus.add_watchpoint(watchpoint);
+                    format!("watching {}", describe_watchpoint(&watchpoint))
+                }),
+                _ => Err("watch needs a kind and an address".to_string()),
+            },
+
+            "unwatch" => match args.first() {
+                Some(arg) => parse_hex(arg).map(|addr| {
+                    let removed = emulator.bus.remove_watchpoints_at(addr);
+                    format!("removed {} watchpoints at {:03X}", removed, addr)
+                }),
+                None => {
+                    emulator.bus.clear_watchpoints();
+                    Ok("removed all watchpoints".to_string())
+                }
+            },
+
+            "r" | "regs" => Ok(registers(emulator)),
+
+            "m" | "mem" => dump_args(&args).and_then(|(addr, len)| dump(emulator, addr, len)),
+
+            "w" | "write" => write_args(&args).and_then(|(addr, bytes)| {
+                for (offset, &byte) in bytes.iter().enumerate() {
+                    let target = addr.wrapping_add(offset as u16);
+                    emulator
+                        .bus
+                        .ram_poke(target, byte)
+                        .map_err(|err| err.to_string())?;
+                }
+                Ok(format!("wrote {} bytes at {:03X}", bytes.len(), addr))
+            }),
+
+            "q" | "quit" => {
+                emulator.change_state(EmulatorState::Quit);
+                Ok("quitting".to_string())
+            }
+
+            _ => Err(format!("unknown command {}, type help", command)),
+        };
+
+        result.unwrap_or_else(|err| format!("error: {}", err))
+    }
+
+    fn run_command(&mut self, emulator: &mut Chip8, line: &str) {
+        let output = self.execute(emulator, line);
+        if !output.is_empty() {
+            println!("{}", output);
+        }
+    }
+
+    fn input_closed(&mut self) {
+        self.input = None;
+        if self.paused {
+            println!("debugger input closed, running");
+            self.paused = false;
+        }
+    }
+
+    // steps while paused and shows where it stopped
+    fn run_for(
+        &mut self,
+        emulator: &mut Chip8,
+        done: impl FnMut(Step) -> bool,
+    ) -> Result<String, String> {
+        self.paused = true;
+
+        match run_until(emulator, &self.breakpoints, true, done) {
+            Ok(Some(stop)) => Ok(format!("{}\n{}", stop, registers(emulator))),
+            Ok(None) => Ok(registers(emulator)),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+
+    fn list_breakpoints(&self) -> String {
+        if self.breakpoints.is_empty() {
+            return "no breakpoints".to_string();
+        }
+
+        let addrs: Vec<String> = self
+            .breakpoints()
+            .map(|addr| format!("{:03X}", addr))
+            .collect();
+        format!("breakpoints: {}", addrs.join(" "))
+    }
+}
+
+/// Why `run_until` stopped before `done`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    /// Before the instruction at this address
+    Breakpoint(u16),
+    /// After `opcode` at `pc` accessed watched memory
+    Watchpoint { hit: WatchHit, pc: u16, opcode: u16 },
+}
+
+/// `breakpoint at 20A` or `watchpoint write 300 = 05 (was 00) by F355 at 21E`
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stop::Breakpoint(pc) => write!(f, "breakpoint at {:03X}", pc),
+            Stop::Watchpoint { hit, pc, opcode } => {
+                write!(f, "watchpoint {} by {:04X} at {:03X}", hit, opcode, pc)
+            }
+        }
+    }
+}
+
+/// Steps until `done`, a breakpoint or a watchpoint hit. With `skip` the
+/// instruction at the current PC runs even if it has a breakpoint
+pub(crate) fn run_until(
+    emulator: &mut Chip8,
+    breakpoints: &BTreeSet<u16>,
+    mut skip: bool,
+    mut done: impl FnMut(Step) -> bool,
+) -> Result<Option<Stop>, Chip8Error> {
+    // hits from before are not ours to report
+    emulator.bus.take_watch_hit();
+
+    loop {
+        if let Some(pc) = emulator.next_instruction() {
+            if !skip && breakpoints.contains(&pc) {
+                return Ok(Some(Stop::Breakpoint(pc)));
+            }
+        }
+
+        let step = emulator.step()?;
+        if let Step::Instruction { pc, opcode } = step {
+            skip = false;
+
+            if let Some(hit) = emulator.bus.take_watch_hit() {
+                return Ok(Some(Stop::Watchpoint { hit, pc, opcode }));
+            }
+        }
+
+        if done(step) {
+            return Ok(None);
+        }
+    }
+}
+
+/// Registers, timers, the stack and the opcode at PC, as the debugger shows them
+pub fn registers(emulator: &Chip8) -> String {
+    let cpu = &emulator.cpu;
+    let pc = cpu.get_pc();
+    let mut out = String::new();
+
+    let _ = write!(
+        out,
+        "PC={:03X} I={:03X} SP={} DT={} ST={}",
+        pc,
+        cpu.get_i_reg_value(),
+        cpu.get_sp(),
+        cpu.get_delay_timer(),
+        cpu.get_sound_timer()
+    );
+
+    let stack: Vec<String> = (0..cpu.get_sp())
+        .map(|slot| format!("{:03X}", cpu.get_stack_value(slot)))
+        .collect();
+    let registers: Vec<String> = (0..16)
+        .map(|x| format!("V{:X}={:02X}", x, cpu.get_vreg_value(x)))
+        .collect();
+
+    let opcode = emulator.bus.ram_peek(pc).and_then(|high| {
+        Ok(u16::from_be_bytes([
+            high,
+            emulator.bus.ram_peek(pc.wrapping_add(1))?,
+        ]))
+    });
+    if let Ok(opcode) = opcode {
+        let _ = write!(out, " opcode={:04X}", opcode);
+    }
+
+    let _ = write!(
+        out,
+        "\n{}\nstack: {}",
+        registers.join(" "),
+        if stack.is_empty() {
+            "empty".to_string()
+        } else {
+            stack.join(" ")
+        }
+    );
+    out
+}
+
+// `len` bytes from `addr`, 16 to a line
+fn dump(emulator: &Chip8, addr: u16, len: usize) -> Result<String, String> {
+    let mut lines = Vec::new();
+
+    for line_start in (0..len).step_by(DUMP_WIDTH) {
+        let start = addr.wrapping_add(line_start as u16);
+        let mut line = format!("{:04X}:", start);
+
+        for offset in 0..DUMP_WIDTH.min(len - line_start) {
+            let byte = emulator
+                .bus
+                .ram_peek(start.wrapping_add(offset as u16))
+                .map_err(|err| err.to_string())?;
+            let _ = write!(line, " {:02X}", byte);
+        }
+
+        lines.push(line);
+    }
+
+    Ok(lines.join("\n"))
+}
+
+fn list_watchpoints(emulator: &Chip8) -> String {
+    let watchpoints = emulator.bus.watchpoints();
+    if watchpoints.is_empty() {
+        return "no watchpoints".to_string();
+    }
+
+    let lines: Vec<String> = watchpoints.iter().map(describe_watchpoint).collect();
+    format!("watchpoints:\n{}", lines.join("\n"))
+}
+
+// `write 300-302` or `change 300`
+fn describe_watchpoint(watchpoint: &Watchpoint) -> String {
+    let last = watchpoint.addr.wrapping_add(watchpoint.len - 1);
+
+    if last == watchpoint.addr {
+        format!("{} {:03X}", watchpoint.kind, watchpoint.addr)
+    } else {
+        format!("{} {:03X}-{:03X}", watchpoint.kind, watchpoint.addr, last)
+    }
+}
+
+fn parse_hex(text: &str) -> Result<u16, String> {
+    let digits = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix("0X"))
+        .unwrap_or(text);
+    u16::from_str_radix(digits, 16).map_err(|_| format!("invalid address {}", text))
+}
+
+fn parse_byte(text: &str) -> Result<u8, String> {
+    let value = parse_hex(text).map_err(|_| format!("invalid byte {}", text))?;
+    u8::try_from(value).map_err(|_| format!("invalid byte {}", text))
+}
+
+fn count_arg(args: &[&str]) -> Result<usize, String> {
+    match args.first() {
+        Some(arg) => match arg.parse() {
+            Ok(count) if count > 0 => Ok(count),
+            _ => Err(format!("invalid count {}", arg)),
+        },
+        None => Ok(1),
+    }
+}
+
+fn dump_args(args: &[&str]) -> Result<(u16, usize), String> {
+    let addr = parse_hex(args.first().ok_or("mem needs an address")?)?;
+    let len = match args.get(1) {
+        Some(arg) => arg.parse().map_err(|_| format!("invalid length {}", arg))?,
+        None => DEFAULT_DUMP_LENGTH,
+    };
+    Ok((addr, len))
+}
+
+fn watch_args(kind: &str, addr: &str, rest: &[&str]) -> Result<Watchpoint, String> {
+    let kind: WatchKind = kind.parse()?;
+    let addr = parse_hex(addr)?;
+    let len = match rest {
+        [] => 1,
+        [len] => len
+            .parse()
+            .ok()
+            .filter(|&len| len > 0)
+            .ok_or_else(|| format!("invalid length {}", len))?,
+        _ => return Err("too many arguments".to_string()),
+    };
+
+    Ok(Watchpoint::init(kind, addr, len))
+}
+
+fn write_args(args: &[&str]) -> Result<(u16, Vec<u8>), String> {
+    let (addr, bytes) = args.split_first().ok_or("write needs an address")?;
+    if bytes.is_empty() {
+        return Err("write needs at least one byte".to_string());
+    }
+
+    let bytes = bytes
+        .iter()
+        .map(|byte| parse_byte(byte))
+        .collect::<Result<_, _>>()?;
+    Ok((parse_hex(addr)?, bytes))
+}
+
+#[cfg(test)]
+mod debugger_tests {
+    use super::*;
+
+    // V0 += 1 forever
+    fn counting() -> Chip8 {
+        let mut emulator = Chip8::init();
+        emulator.load_rom(vec![0x70, 0x01, 0x12, 0x00]).unwrap();
+        emulator
+    }
+
+    #[test]
+    fn test_step_instructions() {
+        let mut emulator = counting();
+        let mut debugger = Debugger::init();
+
+        let output = debugger.execute(&mut emulator, "step 3");
+        assert_eq!(emulator.cpu.get_vreg_value(0), 2);
+        assert!(output.starts_with("PC=202 I=000 SP=0 DT=0 ST=0 opcode=1200\nV0=02 V1=00"));
+        assert!(debugger.is_paused());
+    }
+
+    #[test]
+    fn test_breakpoint_stops_frame() {
+        let mut emulator = counting();
+        let mut debugger = Debugger::init();
+
+        debugger.execute(&mut emulator, "b 0x202");
+        debugger.execute(&mut emulator, "continue");
+        assert!(!debugger.run_frame(&mut emulator).unwrap());
+        assert!(debugger.is_paused());
+        assert_eq!(emulator.cpu.get_pc(), 0x202);
+        assert!(emulator.in_frame());
+
+        // continuing runs the instruction at the breakpoint before stopping again
+        debugger.execute(&mut emulator, "c");
+        assert!(!debugger.run_frame(&mut emulator).unwrap());
+        assert_eq!(emulator.cpu.get_vreg_value(0), 2);
+
+        debugger.execute(&mut emulator, "d");
+        debugger.execute(&mut emulator, "c");
+        assert!(debugger.run_frame(&mut emulator).unwrap());
+    }
+
+    #[test]
+    fn test_frame_step() {
+        let mut emulator = counting();
+        let mut debugger = Debugger::init();
+
+        debugger.execute(&mut emulator, "frame 2");
+        assert!(!emulator.in_frame());
+        // 8 instructions per frame at 500 per second, half of them add one
+        assert_eq!(emulator.cpu.get_vreg_value(0), 8);
+    }
+
+    #[test]
+    fn test_memory_dump_and_write() {
+        let mut emulator = counting();
+        let mut debugger = Debugger::init();
+
+        assert_eq!(
+            debugger.execute(&mut emulator, "write 204 AB cd"),
+            "wrote 2 bytes at 204"
+        );
+        assert_eq!(
+            debugger.execute(&mut emulator, "mem 200 6"),
+            "0200: 70 01 12 00 AB CD"
+        );
+        assert_eq!(
+            debugger.execute(&mut emulator, "m 1FC 20").lines().count(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut emulator = counting();
+        let mut debugger = Debugger::init();
+
+        assert_eq!(
+            debugger.execute(&mut emulator, "jump"),
+            "error: unknown command jump, type help"
+        );
+        assert_eq!(
+            debugger.execute(&mut emulator, "write 200 100"),
+            "error: invalid byte 100"
+        );
+        assert!(debugger
+            .execute(&mut emulator, "mem FFFF 2")
+            .starts_with("error: memory access out of range"));
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        // I = 300, V0 = 5, BCD to 300, V0 = 7, store V0 at 300, loop
+        let mut emulator = Chip8::init();
+        emulator
+            .load_rom(vec![
+                0xA3, 0x00, 0x60, 0x05, 0xF0, 0x33, 0x60, 0x07, 0xF0, 0x55, 0x12, 0x0A,
+            ])
+            .unwrap();
+        let mut debugger = Debugger::init();
+
+        assert_eq!(
+            debugger.execute(&mut emulator, "watch c 300 3"),
+            "watching change 300-302"
+        );
+        // fetching code is not a read
+        debugger.execute(&mut emulator, "watch read 200 12");
+
+        // the BCD only changes its last digit
+        debugger.execute(&mut emulator, "c");
+        assert!(!debugger.run_frame(&mut emulator).unwrap());
+        assert_eq!(emulator.cpu.get_pc(), 0x206);
+        let output = debugger.execute(&mut emulator, "s");
+        assert_eq!(emulator.cpu.get_pc(), 0x208);
+        assert!(output.starts_with("PC=208"));
+
+        let output = debugger.execute(&mut emulator, "s");
+        assert!(output.starts_with("watchpoint write 300 = 07 (was 00) by F055 at 208\n"));
+
+        assert_eq!(
+            debugger.execute(&mut emulator, "unwatch 300"),
+            "removed 1 watchpoints at 300"
+        );
+        debugger.execute(&mut emulator, "c");
+        assert!(debugger.run_frame(&mut emulator).unwrap());
+    }
+
+    #[test]
+    fn test_watch_report() {
+        let mut emulator = Chip8::init();
+        emulator.load_rom(vec![0xA3, 0x00, 0xF0, 0x33]).unwrap();
+        emulator.cpu.set_vreg_value(0, 123);
+        emulator
+            .bus
+            .add_watchpoint(Watchpoint::init(WatchKind::Write, 0x301, 1));
+
+        let stop = run_until(&mut emulator, &BTreeSet::new(), false, |_| false).unwrap();
+        assert_eq!(
+            stop.unwrap().to_string(),
+            "watchpoint write 301 = 02 (was 00) by F033 at 202"
+        );
+    }
+
+    #[test]
+    fn test_stack() {
+        let mut emulator = counting();
+        emulator.exec_instructions(0x2300).unwrap();
+
+        assert!(registers(&emulator).ends_with("stack: 200"));
+    }
+}
@@ -0,0 +1,119 @@
+/// Assembly for `opcode` in the usual Cowgod style, with the SUPER-CHIP
+/// and XO-CHIP additions, e.g. `LD V1, 0x05` or `DRW V0, V1, 5`. Every
+/// instruction set is decoded, so the result does not depend on the
+/// platform. Opcodes that are not instructions come out as `DW 0x1234`.
+pub fn mnemonic(opcode: u16) -> String {
+    let x = (opcode >> 8) & 0xF;
+    let y = (opcode >> 4) & 0xF;
+    let kk = opcode & 0xFF;
+    let nnn = opcode & 0xFFF;
+    let n = opcode & 0xF;
+
+    match opcode >> 12 {
+        0x0 => match opcode {
+            0x00E0 => "CLS".to_string(),
+            0x00EE => "RET".to_string(),
+            0x00FB => "SCR".to_string(),
+            0x00FC => "SCL".to_string(),
+            0x00FD => "EXIT".to_string(),
+            0x00FE => "LOW".to_string(),
+            0x00FF => "HIGH".to_string(),
+            _ if opcode & 0xFFF0 == 0x00C0 => format!("SCD {}", n),
+            _ if opcode & 0xFFF0 == 0x00D0 => format!("SCU {}", n),
+            _ => format!("SYS 0x{:03X}", nnn),
+        },
+        0x1 => format!("JP 0x{:03X}", nnn),
+        0x2 => format!("CALL 0x{:03X}", nnn),
+        0x3 => format!("SE V{:X}, 0x{:02X}", x, kk),
+        0x4 => format!("SNE V{:X}, 0x{:02X}", x, kk),
+        0x5 => match n {
+            0x0 => format!("SE V{:X}, V{:X}", x, y),
+            0x2 => format!("SAVE V{:X}-V{:X}", x, y),
+            0x3 => format!("LOAD V{:X}-V{:X}", x, y),
+            _ => data(opcode),
+        },
+        0x6 => format!("LD V{:X}, 0x{:02X}", x, kk),
+        0x7 => format!("ADD V{:X}, 0x{:02X}", x, kk),
+        0x8 => {
+            let name = match n {
+                0x0 => "LD",
+                0x1 => "OR",
+                0x2 => "AND",
+                0x3 => "XOR",
+                0x4 => "ADD",
+                0x5 => "SUB",
+                0x6 => "SHR",
+                0x7 => "SUBN",
+                0xE => "SHL",
+                _ => return data(opcode),
+            };
+            format!("{} V{:X}, V{:X}", name, x, y)
+        }
+        0x9 if n == 0 => format!("SNE V{:X}, V{:X}", x, y),
+        0xA => format!("LD I, 0x{:03X}", nnn),
+        0xB => format!("JP V0, 0x{:03X}", nnn),
+        0xC => format!("RND V{:X}, 0x{:02X}", x, kk),
+        0xD => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        0xE => match kk {
+            0x9E => format!("SKP V{:X}", x),
+            0xA1 => format!("SKNP V{:X}", x),
+            _ => data(opcode),
+        },
+        0xF => match kk {
+            // the address is in the next two bytes
+            0x00 if x == 0 => "LD I, LONG".to_string(),
+            0x01 => format!("PLANE {}", x),
+            0x02 if x == 0 => "AUDIO".to_string(),
+            0x07 => format!("LD V{:X}, DT", x),
+            0x0A => format!("LD V{:X}, K", x),
+            0x15 => format!("LD DT, V{:X}", x),
+            0x18 => format!("LD ST, V{:X}", x),
+            0x1E => format!("ADD I, V{:X}", x),
+            0x29 => format!("LD F, V{:X}", x),
+            0x30 => format!("LD HF, V{:X}", x),
+            0x33 => format!("LD B, V{:X}", x),
+            0x3A => format!("PITCH V{:X}", x),
+            0x55 => format!("LD [I], V{:X}", x),
+            0x65 => format!("LD V{:X}, [I]", x),
+            0x75 => format!("LD R, V{:X}", x),
+            0x85 => format!("LD V{:X}, R", x),
+            _ => data(opcode),
+        },
+        _ => data(opcode),
+    }
+}
+
+fn data(opcode: u16) -> String {
+    format!("DW 0x{:04X}", opcode)
+}
+
+#[cfg(test)]
+mod disasm_tests {
+    use super::*;
+
+    #[test]
+    fn test_chip8() {
+        assert_eq!(mnemonic(0x00E0), "CLS");
+        assert_eq!(mnemonic(0x1228), "JP 0x228");
+        assert_eq!(mnemonic(0x3A05), "SE VA, 0x05");
+        assert_eq!(mnemonic(0x8AB4), "ADD VA, VB");
+        assert_eq!(mnemonic(0xD015), "DRW V0, V1, 5");
+        assert_eq!(mnemonic(0xF265), "LD V2, [I]");
+    }
+
+    #[test]
+    fn test_extensions() {
+        assert_eq!(mnemonic(0x00C4), "SCD 4");
+        assert_eq!(mnemonic(0x00FF), "HIGH");
+        assert_eq!(mnemonic(0x5123), "LOAD V1-V2");
+        assert_eq!(mnemonic(0xF000), "LD I, LONG");
+        assert_eq!(mnemonic(0xF201), "PLANE 2");
+    }
+
+    #[test]
+    fn test_data() {
+        assert_eq!(mnemonic(0x5001), "DW 0x5001");
+        assert_eq!(mnemonic(0x8008), "DW 0x8008");
+        assert_eq!(mnemonic(0xFFFF), "DW 0xFFFF");
+    }
+}
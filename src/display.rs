@@ -1,22 +1,62 @@
+use crate::error::Chip8Error;
+use crate::savestate::{StateReader, StateWriter};
+
 pub const DISPLAY_WIDTH: usize = 64;
 pub const DISPLAY_HEIGHT: usize = 32;
 pub const SCALE_FACTOR: u32 = 14;
 pub const FG_COLOR: u32 = 0xFFFFFFFF;
 pub const BG_COLOR: u32 = 0x00000000;
 
+/// XO-CHIP bitplanes. Every pixel holds one bit per plane, so pixel values
+/// go from 0 to 3 and index into `PALETTE`
+pub const PLANE_1: u32 = 0b01;
+pub const PLANE_2: u32 = 0b10;
+
+/// Colour of each pixel value, RGBA
+pub const PALETTE: [u32; 4] = [BG_COLOR, FG_COLOR, 0xFF6600FF, 0x662200FF];
+
+// characters `to_text` uses for each pixel value
+const TEXT_PIXELS: [char; 4] = ['.', '#', '+', '*'];
+
+/// Character `Display::to_text` uses for a pixel value
+pub fn pixel_char(pixel: u32) -> char {
+    TEXT_PIXELS[(pixel & 0b11) as usize]
+}
+
+/// Screen of `width * height` pixels. Programs draw on a 64x32 grid, on a
+/// bigger screen every pixel becomes a square block unless high resolution
+/// mode is on, in which case programs draw on every screen pixel.
 pub struct Display {
-    video: [u32; DISPLAY_WIDTH * DISPLAY_HEIGHT],
+    width: usize,
+    height: usize,
+    hires: bool,
+    // planes touched by clear, scroll and draw
+    selected_planes: u32,
+    video: Vec<u32>,
 }
 
 impl Display {
     pub fn init() -> Display {
+        Display::with_size(DISPLAY_WIDTH, DISPLAY_HEIGHT)
+    }
+
+    pub fn with_size(width: usize, height: usize) -> Display {
         Display {
-            video: [0; DISPLAY_WIDTH * DISPLAY_HEIGHT],
+            width,
+            height,
+            hires: false,
+            selected_planes: PLANE_1,
+            video: vec![0; width * height],
         }
     }
 
+    /// Clears the selected planes
     pub fn clear(&mut self) {
-        self.video = [0; DISPLAY_WIDTH * DISPLAY_HEIGHT]
+        let planes = self.selected_planes;
+
+        for pixel in self.video.iter_mut() {
+            *pixel &= !planes;
+        }
     }
 
     pub fn get_pixel(&self, index: usize) -> u32 {
@@ -30,4 +70,154 @@ impl Display {
     pub fn get_buffer(&self) -> &[u32] {
         &self.video
     }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// FNV-1a hash of the screen contents, to compare frames cheaply
+    pub fn hash(&self) -> u64 {
+        let mut hash: u64 = 0xCBF29CE484222325;
+
+        for &pixel in &self.video {
+            hash ^= u64::from(pixel);
+            hash = hash.wrapping_mul(0x100000001B3);
+        }
+
+        hash
+    }
+
+    /// The screen as text, one line per row. Unlit pixels are `.`, lit
+    /// ones `#`, or `+` and `*` for XO-CHIP plane 2 and both planes.
+    pub fn to_text(&self) -> String {
+        let mut text = String::with_capacity((self.width + 1) * self.height);
+
+        for row in self.video.chunks(self.width) {
+            text.extend(row.iter().map(|&pixel| pixel_char(pixel)));
+            text.push('\n');
+        }
+
+        text
+    }
+
+    /// Switches between the 64x32 grid and the full screen. Clears every plane
+    pub fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.video.fill(0);
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+
+    pub fn select_planes(&mut self, planes: u32) {
+        self.selected_planes = planes & (PLANE_1 | PLANE_2);
+    }
+
+    pub fn selected_planes(&self) -> u32 {
+        self.selected_planes
+    }
+
+    /// Moves the selected planes down by `rows` logical pixels
+    pub fn scroll_down(&mut self, rows: usize) {
+        self.shift(0, (rows * self.scale()) as isize);
+    }
+
+    /// Moves the selected planes up by `rows` logical pixels
+    pub fn scroll_up(&mut self, rows: usize) {
+        self.shift(0, -((rows * self.scale()) as isize));
+    }
+
+    /// Moves the selected planes right by `cols` logical pixels
+    pub fn scroll_right(&mut self, cols: usize) {
+        self.shift((cols * self.scale()) as isize, 0);
+    }
+
+    /// Moves the selected planes left by `cols` logical pixels
+    pub fn scroll_left(&mut self, cols: usize) {
+        self.shift(-((cols * self.scale()) as isize), 0);
+    }
+
+    /// Width of the grid programs draw on
+    pub fn logical_width(&self) -> usize {
+        self.width / self.scale()
+    }
+
+    pub fn logical_height(&self) -> usize {
+        self.height / self.scale()
+    }
+
+    /// XORs `plane` of the pixel at logical coordinates (x, y) and returns
+    /// true if that plane was lit before
+    pub fn toggle_pixel(&mut self, x: usize, y: usize, plane: u32) -> bool {
+        let scale = self.scale();
+        let was_lit = self.video[x * scale + y * scale * self.width] & plane != 0;
+
+        for row in y * scale..(y + 1) * scale {
+            for col in x * scale..(x + 1) * scale {
+                self.video[col + row * self.width] ^= plane;
+            }
+        }
+
+        was_lit
+    }
+
+    pub fn save_state(&self, out: &mut StateWriter) {
+        out.put_bool(self.hires);
+        out.put_u8(self.selected_planes as u8);
+        for &pixel in &self.video {
+            out.put_u8(pixel as u8);
+        }
+    }
+
+    /// Screen size is part of the platform, so it already matches
+    pub fn load_state(&mut self, input: &mut StateReader) -> Result<(), Chip8Error> {
+        self.hires = input.get_bool()?;
+        self.select_planes(u32::from(input.get_u8()?));
+
+        let pixels = input.get_bytes(self.video.len())?;
+        for (pixel, &value) in self.video.iter_mut().zip(pixels) {
+            *pixel = u32::from(value) & (PLANE_1 | PLANE_2);
+        }
+
+        Ok(())
+    }
+
+    // size of a logical pixel in screen pixels
+    fn scale(&self) -> usize {
+        if self.hires {
+            1
+        } else {
+            self.width / DISPLAY_WIDTH
+        }
+    }
+
+    // moves the selected planes by (dx, dy) screen pixels, filling the gap with zeros
+    fn shift(&mut self, dx: isize, dy: isize) {
+        let planes = self.selected_planes;
+        let source = self.video.clone();
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let src_x = x as isize - dx;
+                let src_y = y as isize - dy;
+
+                let inside = (0..self.width as isize).contains(&src_x)
+                    && (0..self.height as isize).contains(&src_y);
+
+                let moved = if inside {
+                    source[src_x as usize + src_y as usize * self.width] & planes
+                } else {
+                    0
+                };
+
+                let index = x + y * self.width;
+                self.video[index] = (self.video[index] & !planes) | moved;
+            }
+        }
+    }
 }
@@ -0,0 +1,92 @@
+use std::{error, fmt, io};
+
+/// Everything that can go wrong while loading or running a ROM
+#[derive(Debug)]
+pub enum Chip8Error {
+    /// `pc` is the address the opcode was fetched from
+    InvalidOpcode { opcode: u16, pc: u16 },
+    /// 2nnn with every stack slot already in use
+    StackOverflow { pc: u16 },
+    /// 00EE with nothing on the stack
+    StackUnderflow { pc: u16 },
+    /// Read or write outside of RAM
+    MemoryFault { addr: usize },
+    /// The ROM file could not be read
+    RomLoad(io::Error),
+    /// The ROM does not fit between the program start and the end of RAM
+    RomTooLarge { size: usize, max: usize },
+    /// An output file could not be written
+    Output(io::Error),
+    /// A save state file could not be read or written
+    SaveStateFile(io::Error),
+    /// The data is not a save state or is damaged
+    InvalidSaveState(&'static str),
+    /// The save state was written by another version of the format
+    SaveStateVersion { found: u16, expected: u16 },
+    /// The save state belongs to a machine with another platform configuration
+    SaveStatePlatform,
+    /// A movie file could not be read
+    MovieFile(io::Error),
+    /// The data is not a movie or is damaged
+    InvalidMovie(&'static str),
+    /// The movie was recorded with another ROM or machine configuration
+    MovieMismatch(&'static str),
+    /// The connection to a GDB client failed
+    Gdb(io::Error),
+    /// A trace file could not be read
+    TraceFile(io::Error),
+    /// A trace line without the fields `trace::TraceEntry` needs
+    InvalidTrace { line: usize },
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Chip8Error::InvalidOpcode { opcode, pc } => {
+                write!(f, "unknown opcode {:04X} at {:03X}", opcode, pc)
+            }
+            Chip8Error::StackOverflow { pc } => write!(f, "stack overflow at {:03X}", pc),
+            Chip8Error::StackUnderflow { pc } => write!(f, "stack underflow at {:03X}", pc),
+            Chip8Error::MemoryFault { addr } => write!(f, "memory access out of range: {:X}", addr),
+            Chip8Error::RomLoad(err) => write!(f, "failed to load rom: {}", err),
+            Chip8Error::RomTooLarge { size, max } => {
+                write!(
+                    f,
+                    "rom is {} bytes, at most {} bytes fit in memory",
+                    size, max
+                )
+            }
+            Chip8Error::Output(err) => write!(f, "failed to write output: {}", err),
+            Chip8Error::SaveStateFile(err) => write!(f, "failed to access save state: {}", err),
+            Chip8Error::InvalidSaveState(reason) => write!(f, "invalid save state: {}", reason),
+            Chip8Error::SaveStateVersion { found, expected } => write!(
+                f,
+                "save state has version {}, this build reads version {}",
+                found, expected
+            ),
+            Chip8Error::SaveStatePlatform => {
+                write!(f, "save state was made for a different platform")
+            }
+            Chip8Error::MovieFile(err) => write!(f, "failed to read movie: {}", err),
+            Chip8Error::InvalidMovie(reason) => write!(f, "invalid movie: {}", reason),
+            Chip8Error::MovieMismatch(reason) => write!(f, "movie was recorded {}", reason),
+            Chip8Error::Gdb(err) => write!(f, "gdb connection failed: {}", err),
+            Chip8Error::TraceFile(err) => write!(f, "failed to read trace: {}", err),
+            Chip8Error::InvalidTrace { line } => write!(f, "invalid trace at line {}", line),
+        }
+    }
+}
+
+impl error::Error for Chip8Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Chip8Error::RomLoad(err)
+            | Chip8Error::Output(err)
+            | Chip8Error::SaveStateFile(err)
+            | Chip8Error::MovieFile(err)
+            | Chip8Error::Gdb(err)
+            | Chip8Error::TraceFile(err) => Some(err),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,380 @@
+use std::{
+    path::PathBuf,
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use crate::audio::Audio;
+use crate::capture::{CaptureFormat, FrameCapture};
+use crate::chip8::{Chip8, EmulatorState};
+use crate::debugger::Debugger;
+use crate::error::Chip8Error;
+use crate::gdb::GdbStub;
+use crate::movie::{Player, Recorder};
+use crate::rewind::Rewind;
+use crate::savestate;
+use crate::scheduler::Scheduler;
+use crate::screenshot::{self, ImageFormat, Screenshot};
+
+/// Input the main loop understands, independent of the windowing library
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputEvent {
+    /// CHIP-8 keypad key (0x0 - 0xF) and whether it is held down
+    Key(usize, bool),
+    TogglePause,
+    /// Save the machine to a slot, numbered from 1
+    SaveState(usize),
+    /// Restore the machine from a slot, numbered from 1
+    LoadState(usize),
+    /// Step back in time while held
+    Rewind(bool),
+    /// Save the screen as a PNG next to the ROM, at the size of the window
+    Screenshot,
+    /// Start recording every frame next to the ROM, or stop the recording
+    ToggleCapture(CaptureFormat),
+    /// Pause in the stdin debugger, starting it if needed
+    Debug,
+    Quit,
+}
+
+/// Somewhere to show the display buffer
+pub trait VideoSink {
+    /// `buffer` is `width * height` pixels in row major order
+    fn present(&mut self, buffer: &[u32], width: usize, height: usize);
+}
+
+/// Somewhere to read keypad and control input from
+pub trait InputSource {
+    /// Returns every event that happened since the last call
+    fn poll_events(&mut self) -> Vec<InputEvent>;
+
+    /// Blocks until at least one event happened and returns them. The
+    /// default checks `poll_events` a few times a second
+    fn wait_events(&mut self) -> Vec<InputEvent> {
+        loop {
+            let events = self.poll_events();
+            if !events.is_empty() {
+                return events;
+            }
+
+            sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+/// Somewhere to play sound
+pub trait AudioSink {
+    /// Called once per frame with the XO-CHIP audio state, `playing` is true
+    /// while the sound timer is running
+    fn play_frame(&mut self, audio: &Audio, playing: bool);
+}
+
+/// Applies a single input event to the emulator
+pub fn apply_input(emulator: &mut Chip8, event: InputEvent) {
+    match event {
+        InputEvent::Key(index, state) => emulator.bus.handle_key_press(index, state),
+
+        InputEvent::TogglePause => {
+            if emulator.state == EmulatorState::Running {
+                emulator.change_state(EmulatorState::PAUSED);
+                println!("=== PAUSED ====")
+            } else {
+                emulator.change_state(EmulatorState::Running);
+                println!("=== RUNNING ====")
+            }
+        }
+
+        InputEvent::SaveState(slot) => match state_slot_path(emulator, slot) {
+            Some(path) => match emulator.save_state_file(&path) {
+                Ok(()) => println!("=== SAVED SLOT {} ====", slot),
+                Err(err) => eprintln!("{}", err),
+            },
+            None => eprintln!("no rom file, cannot save slot {}", slot),
+        },
+
+        InputEvent::LoadState(slot) => match state_slot_path(emulator, slot) {
+            Some(path) => match emulator.load_state_file(&path) {
+                Ok(()) => println!("=== LOADED SLOT {} ====", slot),
+                Err(err) => eprintln!("{}", err),
+            },
+            None => eprintln!("no rom file, cannot load slot {}", slot),
+        },
+
+        // the history and the recording live in the main loop, see `run`
+        InputEvent::Rewind(_) | InputEvent::ToggleCapture(_) | InputEvent::Debug => {}
+
+        InputEvent::Screenshot => match emulator.rom_path() {
+            Some(rom) => {
+                let path = screenshot::next_path(rom, "shot", ImageFormat::Png.extension());
+                let display = emulator.bus.get_display();
+                let screenshot = Screenshot::with_scale(Screenshot::window_scale(display));
+
+                match screenshot.save(display, &path) {
+                    Ok(()) => println!("=== SCREENSHOT {} ====", path.display()),
+                    Err(err) => eprintln!("{}", err),
+                }
+            }
+            None => eprintln!("no rom file, cannot save a screenshot"),
+        },
+
+        InputEvent::Quit => emulator.change_state(EmulatorState::Quit),
+    }
+}
+
+// slots live next to the rom file
+fn state_slot_path(emulator: &Chip8, slot: usize) -> Option<PathBuf> {
+    emulator
+        .rom_path()
+        .map(|rom| savestate::slot_path(rom, slot))
+}
+
+/// Movie the main loop records or plays back
+pub enum MovieMode {
+    Off,
+    /// Every keypad transition goes into the recorder
+    Record(Recorder),
+    /// The player drives the keypad, keys from the frontend are ignored
+    Play(Player),
+}
+
+/// What the main loop drives besides the emulator
+pub struct Session {
+    pub movie: MovieMode,
+    /// Runs the emulator when present, see `Debugger`
+    pub debugger: Option<Debugger>,
+    /// Runs the emulator for a GDB client when present, see `GdbStub`
+    pub gdb: Option<GdbStub>,
+}
+
+impl Session {
+    pub fn init() -> Session {
+        Session {
+            movie: MovieMode::Off,
+            debugger: None,
+            gdb: None,
+        }
+    }
+}
+
+/// Main loop without a movie or debugger, see `run_session`
+pub fn run<F>(emulator: &mut Chip8, frontend: &mut F) -> Result<(), Chip8Error>
+where
+    F: VideoSink + InputSource + AudioSink,
+{
+    run_session(emulator, frontend, &mut Session::init())
+}
+
+/// Main loop. Runs the emulator until it quits, reading input from
+/// `frontend` and sending it the picture and sound of every frame.
+/// Frames are paced by a `Scheduler`, and while paused the loop sleeps
+/// until the next input event. A `Rewind` history is recorded every frame
+/// and played backwards while `InputEvent::Rewind` is held, unless a movie
+/// is running. A finished movie playback switches the movie to `Off`.
+/// `InputEvent::ToggleCapture` records every frame, including rewound ones.
+/// With a debugger, which `InputEvent::Debug` starts, or a GDB stub, that
+/// runs the frames and reads its commands between them.
+/// Stops at the first interpreter error and hands it back to the caller.
+pub fn run_session<F>(
+    emulator: &mut Chip8,
+    frontend: &mut F,
+    session: &mut Session,
+) -> Result<(), Chip8Error>
+where
+    F: VideoSink + InputSource + AudioSink,
+{
+    let mut scheduler = Scheduler::init();
+    let mut rewind = Rewind::init();
+    let mut rewinding = false;
+    let mut capture = None;
+    let mut desync_reported = false;
+    let mut last_time = Instant::now();
+
+    while emulator.state != EmulatorState::Quit {
+        for event in frontend.poll_events() {
+            handle_event(emulator, event, session, &mut rewinding, &mut capture);
+        }
+
+        if emulator.state == EmulatorState::PAUSED {
+            for event in frontend.wait_events() {
+                handle_event(emulator, event, session, &mut rewinding, &mut capture);
+            }
+
+            // time spent paused is not caught up afterwards
+            scheduler.reset();
+            last_time = Instant::now();
+            continue;
+        }
+
+        if let Some(debugger) = &mut session.debugger {
+            debugger.poll_commands(emulator);
+        }
+        if let Some(gdb) = &mut session.gdb {
+            gdb.poll(emulator);
+        }
+
+        let now = Instant::now();
+        let frames = scheduler.frames_due(now.duration_since(last_time));
+        last_time = now;
+        let Session {
+            movie,
+            debugger,
+            gdb,
+        } = session;
+
+        // Emulate CHIP8 Instructions, one timer tick per frame
+        // or step back through the snapshots while rewinding
+        for _ in 0..frames {
+            if rewinding {
+                rewind.step_back(emulator)?;
+                frontend.play_frame(emulator.bus.get_audio(), false);
+            } else {
+                if let MovieMode::Play(player) = movie {
+                    player.apply_inputs(emulator);
+                }
+
+                // debuggers may stop in the middle of a frame
+                let finished = match (&mut *debugger, &mut *gdb) {
+                    (Some(debugger), _) => debugger.run_frame(emulator)?,
+                    (None, Some(gdb)) => gdb.run_frame(emulator)?,
+                    (None, None) => {
+                        emulator.run_frame()?;
+                        true
+                    }
+                };
+                if !finished {
+                    break;
+                }
+
+                rewind.record(emulator);
+                frontend.play_frame(emulator.bus.get_audio(), emulator.is_sound_playing());
+
+                match movie {
+                    MovieMode::Off => {}
+                    MovieMode::Record(recorder) => recorder.end_frame(emulator),
+                    MovieMode::Play(player) => {
+                        if let Some(desync) = player.end_frame(emulator) {
+                            if !desync_reported {
+                                eprintln!("{}", desync);
+                                desync_reported = true;
+                            }
+                        }
+
+                        if player.is_finished() {
+                            println!("=== MOVIE FINISHED ====");
+                            *movie = MovieMode::Off;
+                        }
+                    }
+                }
+            }
+
+            // a failing recording stops, the emulator keeps going
+            if let Some(recording) = &mut capture {
+                if let Err(err) = recording.capture(emulator.bus.get_display()) {
+                    eprintln!("{}", err);
+                    capture = None;
+                }
+            }
+
+            // 00FD quit, nothing more to run
+            if emulator.state == EmulatorState::Quit {
+                break;
+            }
+        }
+
+        // Update window with changes
+        if frames > 0 {
+            frontend.present(
+                emulator.get_video_buffer(),
+                emulator.bus.display_width(),
+                emulator.bus.display_height(),
+            );
+        }
+
+        sleep(scheduler.time_until_next_frame());
+    }
+
+    if let Some(recording) = capture {
+        stop_capture(recording);
+    }
+
+    Ok(())
+}
+
+// applies an event, keeping the movie in sync with the keypad
+fn handle_event(
+    emulator: &mut Chip8,
+    event: InputEvent,
+    session: &mut Session,
+    rewinding: &mut bool,
+    capture: &mut Option<FrameCapture>,
+) {
+    match (event, &mut session.movie) {
+        // playback owns the keypad
+        (InputEvent::Key(..), MovieMode::Play(_)) => {}
+
+        (InputEvent::Key(index, state), MovieMode::Record(recorder)) => {
+            recorder.record_key(index, state);
+            apply_input(emulator, event);
+        }
+
+        // jumping around in time or poking memory would break the movie
+        (
+            InputEvent::Rewind(true) | InputEvent::LoadState(_) | InputEvent::Debug,
+            MovieMode::Record(_) | MovieMode::Play(_),
+        ) => eprintln!("not available while a movie is running"),
+
+        (InputEvent::Rewind(held), _) => *rewinding = held,
+
+        (InputEvent::Debug, _) if session.gdb.is_some() => {
+            eprintln!("not available while gdb is attached")
+        }
+
+        (InputEvent::Debug, _) => session
+            .debugger
+            .get_or_insert_with(Debugger::with_stdin)
+            .pause(emulator),
+
+        (InputEvent::ToggleCapture(format), _) => match capture.take() {
+            Some(recording) => stop_capture(recording),
+            None => *capture = start_capture(emulator, format),
+        },
+
+        _ => apply_input(emulator, event),
+    }
+}
+
+// recordings go next to the rom, at the size of the window
+fn start_capture(emulator: &Chip8, format: CaptureFormat) -> Option<FrameCapture> {
+    let Some(rom) = emulator.rom_path() else {
+        eprintln!("no rom file, cannot record");
+        return None;
+    };
+
+    let extension = match format {
+        CaptureFormat::Gif => "gif",
+        CaptureFormat::PngSequence => "",
+    };
+    let path = screenshot::next_path(rom, "rec", extension);
+    let display = emulator.bus.get_display();
+
+    match FrameCapture::start(format, &path, display, Screenshot::window_scale(display)) {
+        Ok(recording) => {
+            println!("=== RECORDING {} ====", path.display());
+            Some(recording)
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            None
+        }
+    }
+}
+
+fn stop_capture(recording: FrameCapture) {
+    let path = recording.path().to_path_buf();
+    let frames = recording.frames();
+
+    match recording.finish() {
+        Ok(()) => println!("=== RECORDED {} FRAMES TO {} ====", frames, path.display()),
+        Err(err) => eprintln!("{}", err),
+    }
+}
@@ -0,0 +1,927 @@
+use std::{
+    collections::BTreeSet,
+    fmt::Write as _,
+    io::{self, Read, Write},
+    mem,
+    net::{Ipv4Addr, TcpListener, TcpStream},
+};
+
+use crate::chip8::{Chip8, EmulatorState, Step};
+use crate::debugger::{self, Stop};
+use crate::error::Chip8Error;
+use crate::watch::{WatchKind, Watchpoint};
+
+// V0 - VF, I, PC, SP, DT, ST in the order of `g` and `target.xml`
+const REGISTER_COUNT: usize = 21;
+const I_REGISTER: usize = 16;
+const PC_REGISTER: usize = 17;
+const SP_REGISTER: usize = 18;
+const DT_REGISTER: usize = 19;
+const ST_REGISTER: usize = 20;
+
+// stop signals
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+
+// Ctrl-C from the client while running
+const INTERRUPT: u8 = 0x03;
+
+const PACKET_SIZE: usize = 0x1000;
+
+/// GDB remote serial protocol server on a local TCP port. Like `Debugger`
+/// it runs the emulator in place of `Chip8::run_frame`, stopping at
+/// breakpoints and watchpoints, and starts halted. Memory goes through `Bus`, and the
+/// registers are V0 - VF, I, PC, SP, DT and ST, described to the client
+/// by `target.xml`.
+pub struct GdbStub {
+    stream: TcpStream,
+    // bytes received but not handled yet
+    received: Vec<u8>,
+    breakpoints: BTreeSet<u16>,
+    halted: bool,
+    // continuing from a breakpoint runs its instruction before checking again
+    skip_breakpoint: bool,
+    connected: bool,
+}
+
+/// What the client sent
+#[derive(Debug, PartialEq, Eq)]
+enum Incoming {
+    Packet(Vec<u8>),
+    Interrupt,
+    // checksum mismatch, the client sends it again
+    Corrupt,
+}
+
+impl GdbStub {
+    /// Waits on 127.0.0.1:`port` for a client to attach
+    pub fn listen(port: u16) -> Result<GdbStub, Chip8Error> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port)).map_err(Chip8Error::Gdb)?;
+        println!(
+            "waiting for gdb on {}",
+            listener.local_addr().map_err(Chip8Error::Gdb)?
+        );
+
+        let (stream, addr) = listener.accept().map_err(Chip8Error::Gdb)?;
+        println!("gdb attached from {}", addr);
+        GdbStub::init(stream)
+    }
+
+    /// Serves a client that is already connected
+    pub fn init(stream: TcpStream) -> Result<GdbStub, Chip8Error> {
+        stream.set_nodelay(true).map_err(Chip8Error::Gdb)?;
+
+        Ok(GdbStub {
+            stream,
+            received: Vec::new(),
+            breakpoints: BTreeSet::new(),
+            halted: true,
+            skip_breakpoint: false,
+            connected: true,
+        })
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// False once the client detached or the connection dropped
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    /// Runs the rest of the frame unless halted, and returns whether the
+    /// frame ended. Halts before an instruction with a breakpoint, and on
+    /// an interpreter error, which the client sees as a signal.
+    pub fn run_frame(&mut self, emulator: &mut Chip8) -> Result<bool, Chip8Error> {
+        if self.halted {
+            return Ok(false);
+        }
+
+        // the watchpoints of a client that left
+        if !self.connected {
+            emulator.bus.clear_watchpoints();
+            emulator.run_frame()?;
+            return Ok(true);
+        }
+
+        let skip = mem::take(&mut self.skip_breakpoint);
+        match debugger::run_until(emulator, &self.breakpoints, skip, |step| {
+            step == Step::FrameEnd
+        }) {
+            Ok(None) => Ok(true),
+            Ok(Some(stop)) => {
+                self.stop(&stop_packet(&stop));
+                Ok(false)
+            }
+            Err(err) => {
+                eprintln!("{}", err);
+                self.stop(&stop_reply(stop_signal(&err)));
+                Ok(false)
+            }
+        }
+    }
+
+    /// Handles everything the client sent since the last call
+    pub fn poll(&mut self, emulator: &mut Chip8) {
+        if !self.connected {
+            return;
+        }
+
+        self.receive(false);
+        while let Some(incoming) = next_incoming(&mut self.received) {
+            self.handle(emulator, incoming);
+        }
+    }
+
+    /// Blocks until the client sent something and handles it. Returns false
+    /// once the client is gone, the emulator then keeps running
+    pub fn wait(&mut self, emulator: &mut Chip8) -> bool {
+        loop {
+            if let Some(incoming) = next_incoming(&mut self.received) {
+                self.handle(emulator, incoming);
+                return self.connected;
+            }
+            if !self.connected {
+                return false;
+            }
+
+            self.receive(true);
+        }
+    }
+
+    // reads what is available, or with `blocking` waits for something
+    fn receive(&mut self, blocking: bool) {
+        if let Err(err) = self.stream.set_nonblocking(!blocking) {
+            self.disconnect(err);
+            return;
+        }
+
+        let mut buffer = [0; 1024];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => {
+                    self.detach("gdb disconnected");
+                    return;
+                }
+                Ok(len) => {
+                    self.received.extend_from_slice(&buffer[..len]);
+                    if blocking {
+                        return;
+                    }
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => {
+                    self.disconnect(err);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn handle(&mut self, emulator: &mut Chip8, incoming: Incoming) {
+        match incoming {
+            Incoming::Packet(packet) => {
+                self.send_raw(b"+");
+                let packet = String::from_utf8_lossy(&packet).into_owned();
+                if let Some(reply) = self.execute(emulator, &packet) {
+                    self.send(&reply);
+                }
+            }
+
+            Incoming::Interrupt if !self.halted => self.stop(&stop_reply(SIGINT)),
+            Incoming::Interrupt => {}
+
+            Incoming::Corrupt => self.send_raw(b"-"),
+        }
+    }
+
+    // the reply to a packet, None when it comes later or never
+    fn execute(&mut self, emulator: &mut Chip8, packet: &str) -> Option<String> {
+        let (command, args) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+
+        let reply = match command {
+            "?" => stop_reply(SIGTRAP),
+
+            "g" => (0..REGISTER_COUNT)
+                .map(|n| encode_register(emulator, n))
+                .collect(),
+
+            "G" => match decode_registers(args) {
+                Some(values) => {
+                    let written = values
+                        .into_iter()
+                        .enumerate()
+                        .try_for_each(|(n, value)| write_register(emulator, n, value));
+                    ok_or_error(written)
+                }
+                None => error(),
+            },
+
+            "p" => match parse_hex(args) {
+                Some(n) if (n as usize) < REGISTER_COUNT => encode_register(emulator, n as usize),
+                _ => error(),
+            },
+
+            "P" => match args.split_once('=').and_then(|(n, value)| {
+                let n = parse_hex(n)? as usize;
+                Some((n, decode_le(value, register_size(n)?)?))
+            }) {
+                Some((n, value)) => ok_or_error(write_register(emulator, n, value)),
+                None => error(),
+            },
+
+            "m" => match parse_range(args) {
+                Some((addr, len)) => read_memory(emulator, addr, len.min(PACKET_SIZE / 2)),
+                None => error(),
+            },
+
+            "M" => match args
+                .split_once(':')
+                .and_then(|(range, data)| Some((parse_range(range)?, decode_hex(data)?)))
+            {
+                Some(((addr, len), bytes)) if bytes.len() == len => {
+                    ok_or_error(write_memory(emulator, addr, &bytes))
+                }
+                _ => error(),
+            },
+
+            "c" | "s" => {
+                if let Some(addr) = parse_hex(args) {
+                    emulator.cpu.update_pc(addr);
+                }
+
+                if command == "c" {
+                    self.halted = false;
+                    self.skip_breakpoint = true;
+                    return None;
+                }
+                return self.single_step(emulator);
+            }
+
+            "Z" | "z" => match parse_breakpoint(args) {
+                // software and hardware breakpoints are the same thing here
+                Some((0 | 1, addr, _)) => {
+                    if command == "Z" {
+                        self.breakpoints.insert(addr);
+                    } else {
+                        self.breakpoints.remove(&addr);
+                    }
+                    "OK".to_string()
+                }
+
+                Some((kind @ 2..=4, addr, len)) => {
+                    let kind = match kind {
+                        2 => WatchKind::Write,
+                        3 => WatchKind::Read,
+                        _ => WatchKind::Access,
+                    };
+                    let watchpoint = Watchpoint::init(kind, addr, len);
+
+                    if command == "Z" {
+                        emulator.bus.add_watchpoint(watchpoint);
+                    } else {
+                        emulator.bus.remove_watchpoint(&watchpoint);
+                    }
+                    "OK".to_string()
+                }
+
+                _ => String::new(),
+            },
+
+            "D" => {
+                self.send("OK");
+                self.detach("gdb detached");
+                return None;
+            }
+
+            "k" => {
+                emulator.change_state(EmulatorState::Quit);
+                self.detach("killed by gdb");
+                return None;
+            }
+
+            "H" => "OK".to_string(),
+
+            "q" => self.query(args),
+
+            // vCont, X and anything else fall back to the basic packets
+            _ => String::new(),
+        };
+
+        Some(reply)
+    }
+
+    fn query(&self, query: &str) -> String {
+        if query.starts_with("Supported") {
+            return format!("PacketSize={:x};qXfer:features:read+", PACKET_SIZE);
+        }
+
+        if let Some(range) = query.strip_prefix("Xfer:features:read:target.xml:") {
+            return match parse_range(range) {
+                Some((offset, len)) => xfer_chunk(target_xml().as_bytes(), offset.into(), len),
+                None => error(),
+            };
+        }
+
+        match query {
+            "Attached" => "1".to_string(),
+            "C" => "QC1".to_string(),
+            "fThreadInfo" => "m1".to_string(),
+            "sThreadInfo" => "l".to_string(),
+            _ => String::new(),
+        }
+    }
+
+    fn single_step(&mut self, emulator: &mut Chip8) -> Option<String> {
+        let stepped = debugger::run_until(emulator, &self.breakpoints, true, |step| {
+            matches!(step, Step::Instruction { .. })
+        });
+
+        match stepped {
+            Ok(Some(stop)) => Some(stop_packet(&stop)),
+            Ok(None) => Some(stop_reply(SIGTRAP)),
+            Err(err) => {
+                eprintln!("{}", err);
+                Some(stop_reply(stop_signal(&err)))
+            }
+        }
+    }
+
+    fn stop(&mut self, reply: &str) {
+        self.halted = true;
+        self.send(reply);
+    }
+
+    fn send(&mut self, data: &str) {
+        let mut packet = Vec::with_capacity(data.len() + 4);
+        packet.push(b'$');
+        for &byte in data.as_bytes() {
+            // binary data escapes the framing characters
+            if matches!(byte, b'$' | b'#' | b'}' | b'*') {
+                packet.extend_from_slice(&[b'}', byte ^ 0x20]);
+            } else {
+                packet.push(byte);
+            }
+        }
+        let checksum = packet[1..].iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+        packet.extend_from_slice(format!("#{:02x}", checksum).as_bytes());
+
+        self.send_raw(&packet);
+    }
+
+    fn send_raw(&mut self, data: &[u8]) {
+        if !self.connected {
+            return;
+        }
+
+        let sent = self
+            .stream
+            .set_nonblocking(false)
+            .and_then(|()| self.stream.write_all(data));
+        if let Err(err) = sent {
+            self.disconnect(err);
+        }
+    }
+
+    fn disconnect(&mut self, err: io::Error) {
+        eprintln!("{}", Chip8Error::Gdb(err));
+        self.detach("gdb disconnected");
+    }
+
+    // the emulator runs on without breakpoints
+    fn detach(&mut self, message: &str) {
+        if self.connected {
+            println!("{}", message);
+        }
+
+        self.connected = false;
+        self.halted = false;
+        self.breakpoints.clear();
+        self.received.clear();
+    }
+}
+
+// takes the next complete packet or interrupt off the front of `received`
+fn next_incoming(received: &mut Vec<u8>) -> Option<Incoming> {
+    loop {
+        match *received.first()? {
+            INTERRUPT => {
+                received.remove(0);
+                return Some(Incoming::Interrupt);
+            }
+
+            b'$' => {
+                let end = received.iter().position(|&b| b == b'#')?;
+                if received.len() < end + 3 {
+                    return None;
+                }
+
+                let packet: Vec<u8> = received.drain(..end + 3).collect();
+                let data = &packet[1..end];
+                let checksum = std::str::from_utf8(&packet[end + 1..])
+                    .ok()
+                    .and_then(|text| u8::from_str_radix(text, 16).ok());
+
+                let sum = data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+                return Some(if checksum == Some(sum) {
+                    Incoming::Packet(unescape(data))
+                } else {
+                    Incoming::Corrupt
+                });
+            }
+
+            // acks and noise between packets
+            _ => {
+                received.remove(0);
+            }
+        }
+    }
+}
+
+fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut bytes = data.iter();
+    let mut out = Vec::with_capacity(data.len());
+
+    while let Some(&byte) = bytes.next() {
+        match byte {
+            b'}' => out.extend(bytes.next().map(|b| b ^ 0x20)),
+            _ => out.push(byte),
+        }
+    }
+    out
+}
+
+fn stop_reply(signal: u8) -> String {
+    format!("S{:02x}", signal)
+}
+
+// watchpoints tell the client which address was accessed
+fn stop_packet(stop: &Stop) -> String {
+    match stop {
+        Stop::Breakpoint(_) => stop_reply(SIGTRAP),
+        Stop::Watchpoint { hit, .. } => {
+            let kind = match hit.watchpoint.kind {
+                WatchKind::Read => "rwatch",
+                WatchKind::Access => "awatch",
+                WatchKind::Write | WatchKind::Change => "watch",
+            };
+            format!("T{:02x}{}:{:x};", SIGTRAP, kind, hit.addr)
+        }
+    }
+}
+
+fn stop_signal(err: &Chip8Error) -> u8 {
+    match err {
+        Chip8Error::InvalidOpcode { .. } => SIGILL,
+        _ => SIGSEGV,
+    }
+}
+
+fn ok_or_error(result: Result<(), Chip8Error>) -> String {
+    match result {
+        Ok(()) => "OK".to_string(),
+        Err(_) => error(),
+    }
+}
+
+fn error() -> String {
+    "E01".to_string()
+}
+
+// bytes per register, None past the last one
+fn register_size(n: usize) -> Option<usize> {
+    match n {
+        I_REGISTER | PC_REGISTER => Some(2),
+        _ if n < REGISTER_COUNT => Some(1),
+        _ => None,
+    }
+}
+
+fn read_register(emulator: &Chip8, n: usize) -> u16 {
+    let cpu = &emulator.cpu;
+
+    match n {
+        I_REGISTER => cpu.get_i_reg_value(),
+        PC_REGISTER => cpu.get_pc(),
+        SP_REGISTER => cpu.get_sp().into(),
+        DT_REGISTER => cpu.get_delay_timer().into(),
+        ST_REGISTER => cpu.get_sound_timer().into(),
+        _ => cpu.get_vreg_value(n as u8).into(),
+    }
+}
+
+fn write_register(emulator: &mut Chip8, n: usize, value: u16) -> Result<(), Chip8Error> {
+    let cpu = &mut emulator.cpu;
+    // one byte registers only get the low byte
+    let byte = value as u8;
+
+    match n {
+        I_REGISTER => cpu.set_i_reg_value(value),
+        PC_REGISTER => cpu.update_pc(value),
+        SP_REGISTER => cpu.set_sp(byte)?,
+        DT_REGISTER => cpu.set_delay_timer(byte),
+        ST_REGISTER => cpu.set_sound_timer(byte),
+        _ => cpu.set_vreg_value(n as u8, byte),
+    }
+    Ok(())
+}
+
+// little endian hex, as gdb expects registers
+fn encode_register(emulator: &Chip8, n: usize) -> String {
+    let value = read_register(emulator, n);
+    let size = register_size(n).unwrap_or(1);
+
+    value.to_le_bytes()[..size]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn decode_registers(data: &str) -> Option<Vec<u16>> {
+    let mut values = Vec::with_capacity(REGISTER_COUNT);
+    let mut rest = data;
+
+    for n in 0..REGISTER_COUNT {
+        let size = register_size(n)? * 2;
+        let (value, tail) = rest.split_at_checked(size)?;
+        values.push(decode_le(value, size / 2)?);
+        rest = tail;
+    }
+
+    rest.is_empty().then_some(values)
+}
+
+fn decode_le(data: &str, size: usize) -> Option<u16> {
+    let bytes = decode_hex(data)?;
+    if bytes.len() != size {
+        return None;
+    }
+
+    Some(
+        bytes
+            .iter()
+            .rev()
+            .fold(0, |value, &b| value << 8 | u16::from(b)),
+    )
+}
+
+fn read_memory(emulator: &Chip8, addr: u16, len: usize) -> String {
+    let mut out = String::with_capacity(len * 2);
+
+    for offset in 0..len {
+        let target = addr.wrapping_add(offset as u16);
+        match emulator.bus.ram_peek(target) {
+            Ok(byte) => write!(out, "{:02x}", byte).unwrap(),
+            // a short read ends at the end of RAM
+            Err(_) if offset > 0 => break,
+            Err(_) => return error(),
+        }
+    }
+    out
+}
+
+fn write_memory(emulator: &mut Chip8, addr: u16, bytes: &[u8]) -> Result<(), Chip8Error> {
+    for (offset, &byte) in bytes.iter().enumerate() {
+        emulator
+            .bus
+            .ram_poke(addr.wrapping_add(offset as u16), byte)?;
+    }
+    Ok(())
+}
+
+fn parse_hex(text: &str) -> Option<u16> {
+    u16::from_str_radix(text, 16).ok()
+}
+
+// `addr,len` in hex
+fn parse_range(text: &str) -> Option<(u16, usize)> {
+    let (addr, len) = text.split_once(',')?;
+    Some((parse_hex(addr)?, usize::from_str_radix(len, 16).ok()?))
+}
+
+// `type,addr,kind` in hex, the kind of a watchpoint is its length
+fn parse_breakpoint(text: &str) -> Option<(u8, u16, u16)> {
+    let mut fields = text.split(',');
+    let kind = fields.next()?.parse().ok()?;
+    let addr = parse_hex(fields.next()?)?;
+    let len = parse_hex(fields.next()?)?;
+    Some((kind, addr, len))
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// `m` with more to come or `l` for the last part of a qXfer object
+fn xfer_chunk(data: &[u8], offset: usize, len: usize) -> String {
+    let start = offset.min(data.len());
+    let end = offset.saturating_add(len).min(data.len());
+    let marker = if end < data.len() { 'm' } else { 'l' };
+
+    format!("{}{}", marker, String::from_utf8_lossy(&data[start..end]))
+}
+
+fn target_xml() -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\"?>\n\
+         <!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n\
+         <target version=\"1.0\">\n\
+         <feature name=\"org.chip8.core\">\n",
+    );
+
+    for n in 0..16 {
+        writeln!(xml, "<reg name=\"v{:x}\" bitsize=\"8\" type=\"uint8\"/>", n).unwrap();
+    }
+    xml.push_str(
+        "<reg name=\"i\" bitsize=\"16\" type=\"data_ptr\"/>\n\
+         <reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\"/>\n\
+         <reg name=\"sp\" bitsize=\"8\" type=\"uint8\"/>\n\
+         <reg name=\"dt\" bitsize=\"8\" type=\"uint8\"/>\n\
+         <reg name=\"st\" bitsize=\"8\" type=\"uint8\"/>\n\
+         </feature>\n\
+         </target>\n",
+    );
+    xml
+}
+
+#[cfg(test)]
+mod gdb_tests {
+    use super::*;
+
+    // client end of a connection and the stub serving it
+    fn connect() -> (TcpStream, GdbStub) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.set_nodelay(true).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        (client, GdbStub::init(stream).unwrap())
+    }
+
+    // V0 counts up forever: 7001 1200
+    fn counting() -> Chip8 {
+        let mut emulator = Chip8::init();
+        emulator.load_rom(vec![0x70, 0x01, 0x12, 0x00]).unwrap();
+        emulator
+    }
+
+    fn send(client: &mut TcpStream, data: &str) {
+        let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        write!(client, "${}#{:02x}", data, checksum).unwrap();
+    }
+
+    // skips acks, checks the checksum and acks the reply
+    fn reply(client: &mut TcpStream) -> String {
+        let mut next = || {
+            let mut byte = [0];
+            client.read_exact(&mut byte).unwrap();
+            byte[0]
+        };
+        while next() != b'$' {}
+
+        let mut data = Vec::new();
+        loop {
+            match next() {
+                b'#' => break,
+                byte => data.push(byte),
+            }
+        }
+        let checksum = vec![next(), next()];
+        let sum = data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+        assert_eq!(checksum, format!("{:02x}", sum).into_bytes());
+
+        client.write_all(b"+").unwrap();
+        String::from_utf8(unescape(&data)).unwrap()
+    }
+
+    // sends a packet, lets the stub handle it and returns the reply
+    fn request(
+        client: &mut TcpStream,
+        stub: &mut GdbStub,
+        emulator: &mut Chip8,
+        data: &str,
+    ) -> String {
+        send(client, data);
+        assert!(stub.wait(emulator));
+        reply(client)
+    }
+
+    #[test]
+    fn test_packet_framing() {
+        let mut received = b"+$m200,2#5d\x03$g#00".to_vec();
+
+        assert_eq!(
+            next_incoming(&mut received),
+            Some(Incoming::Packet(b"m200,2".to_vec()))
+        );
+        assert_eq!(next_incoming(&mut received), Some(Incoming::Interrupt));
+        assert_eq!(next_incoming(&mut received), Some(Incoming::Corrupt));
+        assert_eq!(next_incoming(&mut received), None);
+
+        // half a packet waits for the rest
+        let mut received = b"$g#6".to_vec();
+        assert_eq!(next_incoming(&mut received), None);
+        received.push(b'7');
+        assert_eq!(
+            next_incoming(&mut received),
+            Some(Incoming::Packet(b"g".to_vec()))
+        );
+    }
+
+    #[test]
+    fn test_registers() {
+        let (mut client, mut stub) = connect();
+        let mut emulator = counting();
+        emulator.cpu.set_vreg_value(0xF, 0xAB);
+        emulator.cpu.set_i_reg_value(0x1234);
+        emulator.cpu.set_delay_timer(7);
+
+        let registers = request(&mut client, &mut stub, &mut emulator, "g");
+        assert_eq!(registers, format!("{}ab34120002000700", "00".repeat(15)));
+
+        // I is little endian
+        assert_eq!(
+            request(&mut client, &mut stub, &mut emulator, "p10"),
+            "3412"
+        );
+        assert_eq!(
+            request(&mut client, &mut stub, &mut emulator, "P3=42"),
+            "OK"
+        );
+        assert_eq!(emulator.cpu.get_vreg_value(3), 0x42);
+        assert_eq!(
+            request(&mut client, &mut stub, &mut emulator, "P11=0603"),
+            "OK"
+        );
+        assert_eq!(emulator.cpu.get_pc(), 0x306);
+
+        // a stack pointer past the stack is refused
+        assert_eq!(
+            request(&mut client, &mut stub, &mut emulator, "P12=ff"),
+            "E01"
+        );
+        assert_eq!(request(&mut client, &mut stub, &mut emulator, "p15"), "E01");
+
+        let mut written = registers.clone();
+        written.replace_range(0..2, "99");
+        assert_eq!(
+            request(
+                &mut client,
+                &mut stub,
+                &mut emulator,
+                &format!("G{}", written)
+            ),
+            "OK"
+        );
+        assert_eq!(emulator.cpu.get_vreg_value(0), 0x99);
+        assert_eq!(emulator.cpu.get_pc(), 0x200);
+    }
+
+    #[test]
+    fn test_memory() {
+        let (mut client, mut stub) = connect();
+        let mut emulator = counting();
+
+        assert_eq!(
+            request(&mut client, &mut stub, &mut emulator, "m200,4"),
+            "70011200"
+        );
+        assert_eq!(
+            request(&mut client, &mut stub, &mut emulator, "M300,2:aabb"),
+            "OK"
+        );
+        assert_eq!(emulator.bus.ram_read_byte(0x301).unwrap(), 0xBB);
+
+        // reads stop at the end of RAM, writes past it fail
+        let end = format!("m{:x},4", emulator.bus.ram_size() - 1);
+        assert_eq!(
+            request(&mut client, &mut stub, &mut emulator, &end).len(),
+            2
+        );
+        assert_eq!(
+            request(&mut client, &mut stub, &mut emulator, "M2000,1:00"),
+            "E01"
+        );
+    }
+
+    #[test]
+    fn test_breakpoint_and_step() {
+        let (mut client, mut stub) = connect();
+        let mut emulator = counting();
+
+        assert_eq!(
+            request(&mut client, &mut stub, &mut emulator, "Z0,202,2"),
+            "OK"
+        );
+        assert_eq!(request(&mut client, &mut stub, &mut emulator, "s"), "S05");
+        assert_eq!(emulator.cpu.get_pc(), 0x202);
+        assert_eq!(emulator.cpu.get_vreg_value(0), 1);
+
+        // continue runs the breakpoint instruction, then stops at it again
+        send(&mut client, "c");
+        assert!(stub.wait(&mut emulator));
+        assert!(!stub.is_halted());
+        assert!(!stub.run_frame(&mut emulator).unwrap());
+        assert_eq!(reply(&mut client), "S05");
+        assert!(stub.is_halted());
+        assert_eq!(emulator.cpu.get_pc(), 0x202);
+        assert_eq!(emulator.cpu.get_vreg_value(0), 2);
+
+        // without the breakpoint the frame ends
+        assert_eq!(
+            request(&mut client, &mut stub, &mut emulator, "z0,202,2"),
+            "OK"
+        );
+        send(&mut client, "c");
+        assert!(stub.wait(&mut emulator));
+        assert!(stub.run_frame(&mut emulator).unwrap());
+    }
+
+    #[test]
+    fn test_watchpoint() {
+        let (mut client, mut stub) = connect();
+        // I = 300, store V0 at 300, loop
+        let mut emulator = Chip8::init();
+        emulator
+            .load_rom(vec![0xA3, 0x00, 0xF0, 0x55, 0x12, 0x04])
+            .unwrap();
+
+        assert_eq!(
+            request(&mut client, &mut stub, &mut emulator, "Z2,300,1"),
+            "OK"
+        );
+        send(&mut client, "c");
+        assert!(stub.wait(&mut emulator));
+        assert!(!stub.run_frame(&mut emulator).unwrap());
+        assert_eq!(reply(&mut client), "T05watch:300;");
+        assert_eq!(emulator.cpu.get_pc(), 0x204);
+
+        assert_eq!(
+            request(&mut client, &mut stub, &mut emulator, "z2,300,1"),
+            "OK"
+        );
+        assert!(emulator.bus.watchpoints().is_empty());
+    }
+
+    #[test]
+    fn test_interrupt_and_detach() {
+        let (mut client, mut stub) = connect();
+        let mut emulator = counting();
+
+        send(&mut client, "c");
+        assert!(stub.wait(&mut emulator));
+        client.write_all(&[INTERRUPT]).unwrap();
+        assert!(stub.wait(&mut emulator));
+        assert!(stub.is_halted());
+        assert_eq!(reply(&mut client), "S02");
+
+        assert_eq!(
+            request(&mut client, &mut stub, &mut emulator, "qAttached"),
+            "1"
+        );
+        assert_eq!(request(&mut client, &mut stub, &mut emulator, "vCont?"), "");
+
+        send(&mut client, "D");
+        assert!(!stub.wait(&mut emulator));
+        assert_eq!(reply(&mut client), "OK");
+        assert!(!stub.is_connected());
+        assert!(stub.run_frame(&mut emulator).unwrap());
+    }
+
+    #[test]
+    fn test_target_description() {
+        let (mut client, mut stub) = connect();
+        let mut emulator = counting();
+
+        let supported = request(
+            &mut client,
+            &mut stub,
+            &mut emulator,
+            "qSupported:xmlRegisters=i386",
+        );
+        assert!(supported.contains("qXfer:features:read+"));
+
+        let mut xml = String::new();
+        loop {
+            let query = format!("qXfer:features:read:target.xml:{:x},40", xml.len());
+            let chunk = request(&mut client, &mut stub, &mut emulator, &query);
+            xml.push_str(&chunk[1..]);
+            if chunk.starts_with('l') {
+                break;
+            }
+        }
+
+        assert_eq!(xml, target_xml());
+        assert_eq!(xml.matches("<reg ").count(), REGISTER_COUNT);
+    }
+}
@@ -0,0 +1,54 @@
+//! CHIP-8 interpreter core.
+//!
+//! Everything needed to load and run a ROM lives here and has no SDL
+//! dependency. The SDL window is a separate binary behind the `sdl` feature.
+
+pub mod audio;
+pub mod bus;
+pub mod capture;
+pub mod chip8;
+pub mod cpu;
+pub mod debugger;
+pub mod disasm;
+pub mod display;
+pub mod error;
+pub mod frontend;
+pub mod gdb;
+pub mod movie;
+pub mod named;
+pub mod options;
+pub mod platform;
+pub mod quirks;
+pub mod ram;
+pub mod rewind;
+pub mod rng;
+pub mod savestate;
+pub mod scheduler;
+pub mod screenshot;
+pub mod timing;
+pub mod trace;
+pub mod watch;
+pub mod wav;
+
+pub use audio::{Audio, Buzzer, NullAudio, SampleGenerator, Waveform};
+pub use bus::Bus;
+pub use capture::{CaptureFormat, FrameCapture, GifWriter};
+pub use chip8::{Chip8, EmulatorState, Snapshot, Step};
+pub use cpu::CPU;
+pub use debugger::Debugger;
+pub use display::Display;
+pub use error::Chip8Error;
+pub use gdb::GdbStub;
+pub use movie::Movie;
+pub use options::MachineOptions;
+pub use platform::{Platform, PlatformConfig};
+pub use quirks::Quirks;
+pub use ram::RAM;
+pub use rewind::Rewind;
+pub use rng::{RandomModel, Rng};
+pub use scheduler::Scheduler;
+pub use screenshot::{ImageFormat, Screenshot};
+pub use timing::Timing;
+pub use trace::{TraceFilter, TraceOptions, Tracer};
+pub use watch::{WatchHit, WatchKind, Watchpoint};
+pub use wav::WavWriter;
@@ -1,69 +1,248 @@
 #![allow(unused)]
 
-use std::{
-    env,
-    fs::File,
-    io::Read,
-    thread::sleep,
-    time::{Duration, Instant},
-};
+use std::{env, process};
 
-use crate::chip8::EmulatorState;
+use chip_8::{
+    audio::{self, SampleGenerator},
+    chip8,
+    debugger::Debugger,
+    error::Chip8Error,
+    frontend::{self, MovieMode, Session},
+    gdb::GdbStub,
+    movie::{Movie, Player, Recorder},
+    options::MachineOptions,
+    rng::Rng,
+    trace::{self, TraceOptions, Tracer},
+    wav::{self, WavWriter},
+};
 
-mod bus;
-mod chip8;
-mod cpu;
-mod display;
-mod ram;
 mod sdlh;
 
+const USAGE: &str = "usage: chip-8 <rom> [--platform vip|chip48|schip|xochip] [--ips <n>]
+             [--timing flat|vip] [--seed <n>] [--random splitmix|page-sum]
+             [--state <file>] [--record <movie> | --play <movie> | --debug | --gdb <port>]
+             [--waveform square|triangle|sawtooth|sine] [--frequency <hz>] [--volume <0-1>]
+             [--wav <file> [--frames <n>]]
+             [--trace <file> [--trace-pc <start-end>] [--trace-op <0-F,...>]
+                             [--trace-frames <start-end>]]";
+
+// frames recorded by --wav when --frames is not given, ten seconds
+const DEFAULT_WAV_FRAMES: usize = 600;
+
+struct Options {
+    rom: String,
+    machine: MachineOptions,
+    record: Option<String>,
+    play: Option<String>,
+    // start paused in the stdin debugger
+    debug: bool,
+    // wait for a GDB client on this local port
+    gdb: Option<u16>,
+    // write the audio of a headless run here instead of opening a window
+    wav: Option<String>,
+    frames: usize,
+    trace: TraceOptions,
+}
+
 fn main() {
-    let mut sdl_handler = sdlh::SDLHandler::init();
-    let mut chip = chip8::Chip8::init();
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}\n{}", err, USAGE);
+            process::exit(1);
+        }
+    };
 
-    let data = open_file();
-    chip.load_rom(data);
+    let mut config = options.machine.config();
 
-    let target_fps = 60;
-    let frame_duration = Duration::from_secs_f64(1.0 / f64::from(target_fps));
+    // a movie from power-on needs to know the seed
+    if options.record.is_some() && config.seed.is_none() {
+        config.seed = Some(Rng::from_entropy().next_u64());
+    }
 
-    // main game loop
-    while chip.state != chip8::EmulatorState::Quit {
-        sdl_handler.handle_events(&mut chip);
+    let mut chip = chip8::Chip8::with_config(config);
 
-        if chip.state == EmulatorState::PAUSED {
-            continue;
+    println!("{}", options.rom);
+    if let Err(err) = chip.load_rom_file(&options.rom) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+
+    // before --wav, so the recording starts from the state too
+    if let Some(path) = &options.machine.state {
+        if let Err(err) = chip.load_state_file(path) {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    }
+
+    match options.trace.create_tracer() {
+        Ok(Some(tracer)) => chip.set_tracer(tracer),
+        Ok(None) => {}
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    }
+
+    if let Some(path) = &options.wav {
+        let generator = SampleGenerator::with_buzzer(audio::SAMPLE_RATE, options.machine.buzzer);
+        let recorded = wav::record_audio(
+            &mut chip,
+            options.frames,
+            WavWriter::with_generator(generator),
+        );
+
+        if let Err(err) = recorded.and_then(|writer| writer.save(path).map_err(Chip8Error::Output))
+        {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+
+        finish_trace(&mut chip);
+        return;
+    }
+
+    let movie = match (&options.record, &options.play) {
+        (Some(_), _) if options.machine.state.is_some() => {
+            MovieMode::Record(Recorder::from_state(&chip))
         }
+        (Some(_), _) => MovieMode::Record(Recorder::from_power_on(&chip, config.seed.unwrap_or(0))),
+        (None, Some(path)) => {
+            let mut player = match Movie::load(path) {
+                Ok(movie) => Player::init(movie),
+                Err(err) => {
+                    eprintln!("{}", err);
+                    process::exit(1);
+                }
+            };
 
-        let mut last_frame_time = Instant::now();
+            if let Err(err) = player.start(&mut chip) {
+                eprintln!("{}", err);
+                process::exit(1);
+            }
 
-        // Emulate CHIP8 Instructions
-        for i in 0..(chip8::INSTRUCTIONS_PER_SECOND / 60) {
-            chip.run();
+            MovieMode::Play(player)
         }
+        (None, None) => MovieMode::Off,
+    };
 
-        // Delay
-        let elapsed_time = Instant::now().duration_since(last_frame_time);
-        if elapsed_time < frame_duration {
-            sleep(frame_duration - elapsed_time);
+    let mut sdl_handler = sdlh::SDLHandler::init(options.machine.buzzer);
+
+    let mut session = Session {
+        movie,
+        debugger: None,
+        gdb: None,
+    };
+    if options.debug {
+        session
+            .debugger
+            .insert(Debugger::with_stdin())
+            .pause(&mut chip);
+    }
+    if let Some(port) = options.gdb {
+        match GdbStub::listen(port) {
+            Ok(gdb) => session.gdb = Some(gdb),
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(1);
+            }
+        }
+    }
+
+    let result = frontend::run_session(&mut chip, &mut sdl_handler, &mut session);
+
+    // keep what was recorded even if the rom crashed
+    if let (MovieMode::Record(recorder), Some(path)) = (session.movie, &options.record) {
+        if let Err(err) = recorder.finish().save(path) {
+            eprintln!("{}", err);
         }
+    }
+
+    // keep the trace up to a crash, it shows what led there
+    finish_trace(&mut chip);
+
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
 
-        // Update window with changes
-        chip.update_timer();
-        sdl_handler.update_screen(&mut chip);
+fn finish_trace(chip: &mut chip8::Chip8) {
+    if let Some(Err(err)) = chip.take_tracer().map(Tracer::finish) {
+        eprintln!("{}", err);
     }
 }
 
-fn open_file() -> Vec<u8> {
-    let args: Vec<String> = env::args().collect();
-    if (args.len() < 2) {
-        eprintln!("CHIP8 ROM path is required")
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut rom = None;
+    let mut machine = MachineOptions::init();
+    let mut record = None;
+    let mut play = None;
+    let mut debug = false;
+    let mut gdb = None;
+    let mut wav = None;
+    let mut frames = DEFAULT_WAV_FRAMES;
+    let mut trace = TraceOptions::init();
+
+    while let Some(arg) = args.next() {
+        if machine.parse_arg(&arg, &mut args)? || trace.parse_arg(&arg, &mut args)? {
+            continue;
+        }
+
+        match arg.as_str() {
+            "--record" => {
+                record = Some(args.next().ok_or("--record needs a file name")?);
+            }
+            "--play" => {
+                play = Some(args.next().ok_or("--play needs a file name")?);
+            }
+            "--debug" => debug = true,
+            "--gdb" => {
+                let value = args.next().ok_or("--gdb needs a port")?;
+                gdb = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("invalid port {}", value))?,
+                );
+            }
+            "--wav" => {
+                wav = Some(args.next().ok_or("--wav needs a file name")?);
+            }
+            "--frames" => {
+                let value = args.next().ok_or("--frames needs a value")?;
+                frames = value
+                    .parse()
+                    .map_err(|_| format!("invalid frame count {}", value))?;
+            }
+            _ if rom.is_none() && !arg.starts_with("--") => rom = Some(arg),
+            _ => return Err(format!("unexpected argument {}", arg)),
+        }
+    }
+
+    if record.is_some() && play.is_some() {
+        return Err("--record and --play cannot be used together".to_string());
+    }
+
+    if (debug || gdb.is_some()) && (record.is_some() || play.is_some()) {
+        return Err("--debug and --gdb cannot be used with a movie".to_string());
+    }
+
+    if debug && gdb.is_some() {
+        return Err("--debug and --gdb cannot be used together".to_string());
     }
 
-    println!("{}", args[1]);
-    let mut file = File::open(&args[1]).expect("failed to open file");
-    let mut data = Vec::<u8>::new();
-    file.read_to_end(&mut data).unwrap();
+    trace.check()?;
 
-    data
+    Ok(Options {
+        rom: rom.ok_or("CHIP8 ROM path is required")?,
+        machine,
+        record,
+        play,
+        debug,
+        gdb,
+        wav,
+        frames,
+        trace,
+    })
 }
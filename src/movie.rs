@@ -0,0 +1,459 @@
+use std::{fmt, fs, path::Path};
+
+use crate::{
+    chip8::Chip8,
+    error::Chip8Error,
+    platform::PlatformConfig,
+    rng::Rng,
+    savestate::{self, StateReader, StateWriter},
+    timing::Timing,
+};
+
+/// First bytes of every movie file
+pub const MAGIC: &[u8; 8] = b"CH8MOVIE";
+
+/// Bumped whenever the layout changes, older files are rejected
+pub const VERSION: u16 = 2;
+
+/// What the machine looked like when recording started
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MovieStart {
+    /// Freshly loaded ROM with the random number generator seeded with `seed`
+    PowerOn { seed: u64 },
+    /// A save state from `Chip8::save_state`
+    SaveState(Vec<u8>),
+}
+
+/// A keypad transition, applied right before frame `frame` runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyInput {
+    pub frame: u32,
+    pub key: u8,
+    pub pressed: bool,
+}
+
+/// Recorded session: the machine and ROM it ran on, where it started, every
+/// key transition and the hash of the screen after every frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    /// `machine_id` of the recording machine
+    pub machine: Vec<u8>,
+    /// `rom_hash` of the ROM it ran
+    pub rom_hash: u64,
+    pub start: MovieStart,
+    pub inputs: Vec<KeyInput>,
+    pub frame_hashes: Vec<u64>,
+}
+
+impl Movie {
+    pub fn frames(&self) -> usize {
+        self.frame_hashes.len()
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = StateWriter::init();
+        out.put_bytes(MAGIC);
+        out.put_u16(VERSION);
+        out.put_u32(self.machine.len() as u32);
+        out.put_bytes(&self.machine);
+        out.put_u64(self.rom_hash);
+
+        match &self.start {
+            MovieStart::PowerOn { seed } => {
+                out.put_u8(0);
+                out.put_u64(*seed);
+            }
+            MovieStart::SaveState(state) => {
+                out.put_u8(1);
+                out.put_u32(state.len() as u32);
+                out.put_bytes(state);
+            }
+        }
+
+        out.put_u32(self.inputs.len() as u32);
+        for input in &self.inputs {
+            out.put_u32(input.frame);
+            out.put_u8(input.key);
+            out.put_bool(input.pressed);
+        }
+
+        out.put_u32(self.frame_hashes.len() as u32);
+        for &hash in &self.frame_hashes {
+            out.put_u64(hash);
+        }
+
+        out.into_bytes()
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Movie, Chip8Error> {
+        let mut input = StateReader::init(data);
+
+        if input.get_bytes(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+            return Err(Chip8Error::InvalidMovie("not a movie file"));
+        }
+
+        let version = input.get_u16()?;
+        if version != VERSION {
+            return Err(Chip8Error::InvalidMovie("unsupported movie version"));
+        }
+
+        let len = input.get_u32()? as usize;
+        let machine = input.get_bytes(len)?.to_vec();
+        let rom_hash = input.get_u64()?;
+
+        let start = match input.get_u8()? {
+            0 => MovieStart::PowerOn {
+                seed: input.get_u64()?,
+            },
+            1 => {
+                let len = input.get_u32()? as usize;
+                MovieStart::SaveState(input.get_bytes(len)?.to_vec())
+            }
+            _ => return Err(Chip8Error::InvalidMovie("unknown start kind")),
+        };
+
+        let count = input.get_u32()?;
+        let mut inputs = Vec::new();
+        for _ in 0..count {
+            let key_input = KeyInput {
+                frame: input.get_u32()?,
+                key: input.get_u8()?,
+                pressed: input.get_bool()?,
+            };
+
+            if key_input.key > 0xF {
+                return Err(Chip8Error::InvalidMovie("key out of range"));
+            }
+            inputs.push(key_input);
+        }
+
+        let count = input.get_u32()?;
+        let mut frame_hashes = Vec::new();
+        for _ in 0..count {
+            frame_hashes.push(input.get_u64()?);
+        }
+
+        input.finish()?;
+
+        Ok(Movie {
+            machine,
+            rom_hash,
+            start,
+            inputs,
+            frame_hashes,
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Chip8Error> {
+        fs::write(path, self.encode()).map_err(Chip8Error::Output)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Movie, Chip8Error> {
+        let data = fs::read(path).map_err(Chip8Error::MovieFile)?;
+        Movie::decode(&data)
+    }
+}
+
+/// Everything about `config` that changes how a movie plays back: the save
+/// state platform block, the instruction rate and the timing
+pub fn machine_id(config: &PlatformConfig) -> Vec<u8> {
+    let mut out = StateWriter::init();
+    savestate::write_platform(&mut out, config);
+    out.put_u32(config.instructions_per_second);
+    out.put_u8(match config.timing {
+        Timing::Flat => 0,
+        Timing::CosmacVip => 1,
+    });
+    out.into_bytes()
+}
+
+/// FNV-1a hash identifying a ROM
+pub fn rom_hash(rom: &[u8]) -> u64 {
+    let mut hash: u64 = 0xCBF29CE484222325;
+
+    for &byte in rom {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x100000001B3);
+    }
+
+    hash
+}
+
+/// Builds a movie while the emulator runs
+pub struct Recorder {
+    movie: Movie,
+}
+
+impl Recorder {
+    /// Starts from power-on. `emulator` must have been created with `seed`
+    /// and have nothing but the ROM loaded.
+    pub fn from_power_on(emulator: &Chip8, seed: u64) -> Recorder {
+        Recorder::with_start(emulator, MovieStart::PowerOn { seed })
+    }
+
+    /// Starts from the current state of `emulator`
+    pub fn from_state(emulator: &Chip8) -> Recorder {
+        Recorder::with_start(emulator, MovieStart::SaveState(emulator.save_state()))
+    }
+
+    fn with_start(emulator: &Chip8, start: MovieStart) -> Recorder {
+        Recorder {
+            movie: Movie {
+                machine: machine_id(&emulator.config),
+                rom_hash: rom_hash(emulator.rom()),
+                start,
+                inputs: Vec::new(),
+                frame_hashes: Vec::new(),
+            },
+        }
+    }
+
+    /// Records a key transition, it belongs to the next frame
+    pub fn record_key(&mut self, key: usize, pressed: bool) {
+        self.movie.inputs.push(KeyInput {
+            frame: self.movie.frame_hashes.len() as u32,
+            key: key as u8,
+            pressed,
+        });
+    }
+
+    /// Called after every frame
+    pub fn end_frame(&mut self, emulator: &Chip8) {
+        self.movie.frame_hashes.push(emulator.bus.display_hash());
+    }
+
+    pub fn finish(self) -> Movie {
+        self.movie
+    }
+}
+
+/// Screen after `frame` did not match the recording
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Desync {
+    pub frame: u32,
+    pub expected: u64,
+    pub found: u64,
+}
+
+impl fmt::Display for Desync {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "movie desync at frame {}: display hash {:016x}, recorded {:016x}",
+            self.frame, self.found, self.expected
+        )
+    }
+}
+
+/// Feeds a movie's key transitions back into the emulator
+pub struct Player {
+    movie: Movie,
+    frame: u32,
+    next_input: usize,
+}
+
+impl Player {
+    pub fn init(movie: Movie) -> Player {
+        Player {
+            movie,
+            frame: 0,
+            next_input: 0,
+        }
+    }
+
+    /// Puts `emulator` where the recording started, after checking it has
+    /// the same ROM and machine configuration. A power-on movie resets it.
+    pub fn start(&mut self, emulator: &mut Chip8) -> Result<(), Chip8Error> {
+        if machine_id(&emulator.config) != self.movie.machine {
+            return Err(Chip8Error::MovieMismatch(
+                "on a different platform, quirks, timing or random model",
+            ));
+        }
+        if rom_hash(emulator.rom()) != self.movie.rom_hash {
+            return Err(Chip8Error::MovieMismatch("with a different rom"));
+        }
+
+        match &self.movie.start {
+            MovieStart::PowerOn { seed } => {
+                emulator.reset()?;
+                emulator.rng = Rng::from_seed(*seed);
+            }
+            MovieStart::SaveState(state) => emulator.load_state(state)?,
+        }
+
+        self.frame = 0;
+        self.next_input = 0;
+        Ok(())
+    }
+
+    /// Presses and releases the keys recorded for the next frame
+    pub fn apply_inputs(&mut self, emulator: &mut Chip8) {
+        while let Some(input) = self.movie.inputs.get(self.next_input) {
+            if input.frame > self.frame {
+                break;
+            }
+
+            emulator
+                .bus
+                .handle_key_press(input.key as usize, input.pressed);
+            self.next_input += 1;
+        }
+    }
+
+    /// Called after every frame, compares the screen with the recording
+    pub fn end_frame(&mut self, emulator: &Chip8) -> Option<Desync> {
+        let expected = *self.movie.frame_hashes.get(self.frame as usize)?;
+        let found = emulator.bus.display_hash();
+        let frame = self.frame;
+        self.frame += 1;
+
+        if expected == found {
+            None
+        } else {
+            Some(Desync {
+                frame,
+                expected,
+                found,
+            })
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.frame as usize >= self.movie.frames()
+    }
+}
+
+/// Plays a whole movie without a window. Returns the first desync, if any.
+pub fn play(emulator: &mut Chip8, movie: Movie) -> Result<Option<Desync>, Chip8Error> {
+    let mut player = Player::init(movie);
+    player.start(emulator)?;
+
+    while !player.is_finished() {
+        player.apply_inputs(emulator);
+        emulator.run_frame()?;
+
+        if let Some(desync) = player.end_frame(emulator) {
+            return Ok(Some(desync));
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod movie_tests {
+    use super::*;
+    use crate::platform::PlatformConfig;
+
+    // V0 = key, wait for it, draw its digit at (0, 0), jump back
+    const KEY_ROM: [u8; 10] = [0xF0, 0x0A, 0x00, 0xE0, 0xF0, 0x29, 0xD1, 0x15, 0x12, 0x00];
+
+    fn power_on(seed: u64) -> Chip8 {
+        let mut emulator = Chip8::with_config(PlatformConfig {
+            seed: Some(seed),
+            ..PlatformConfig::default()
+        });
+        emulator.load_rom(KEY_ROM.to_vec()).unwrap();
+        emulator
+    }
+
+    fn record(emulator: &mut Chip8, mut recorder: Recorder) -> Movie {
+        // key 1 down and up again, then key 4 down
+        let transitions = [(5, 1, true), (8, 1, false), (12, 4, true)];
+
+        for frame in 0..20 {
+            for &(at, key, pressed) in &transitions {
+                if at == frame {
+                    emulator.bus.handle_key_press(key, pressed);
+                    recorder.record_key(key, pressed);
+                }
+            }
+
+            emulator.run_frame().unwrap();
+            recorder.end_frame(emulator);
+        }
+
+        recorder.finish()
+    }
+
+    #[test]
+    fn test_record_and_play_back() {
+        let mut emulator = power_on(1);
+        let recorder = Recorder::from_power_on(&emulator, 1);
+        let movie = record(&mut emulator, recorder);
+        assert_eq!(movie.inputs.len(), 3);
+
+        let decoded = Movie::decode(&movie.encode()).unwrap();
+        assert_eq!(decoded, movie);
+
+        assert_eq!(play(&mut power_on(1), decoded).unwrap(), None);
+    }
+
+    #[test]
+    fn test_start_from_state() {
+        let mut emulator = power_on(1);
+        emulator.run_frame().unwrap();
+
+        let recorder = Recorder::from_state(&emulator);
+        let movie = record(&mut emulator, recorder);
+        assert_eq!(play(&mut power_on(2), movie).unwrap(), None);
+    }
+
+    #[test]
+    fn test_desync() {
+        let mut emulator = power_on(1);
+        let recorder = Recorder::from_power_on(&emulator, 1);
+        let mut movie = record(&mut emulator, recorder);
+        movie.inputs[2].key = 9;
+
+        // frame 12 happens to end right after a clear, so the wrong digit shows one frame later
+        let desync = play(&mut power_on(1), movie).unwrap().unwrap();
+        assert_eq!(desync.frame, 13);
+    }
+
+    #[test]
+    fn test_power_on_resets() {
+        let mut emulator = power_on(1);
+        let recorder = Recorder::from_power_on(&emulator, 1);
+        let movie = record(&mut emulator, recorder);
+
+        // whatever ran before, playback starts from a fresh machine
+        let mut used = power_on(2);
+        used.bus.handle_key_press(7, true);
+        used.run_frame().unwrap();
+        used.bus.handle_key_press(7, false);
+        assert_eq!(play(&mut used, movie).unwrap(), None);
+    }
+
+    #[test]
+    fn test_rejects_other_machine() {
+        let mut emulator = power_on(1);
+        let recorder = Recorder::from_power_on(&emulator, 1);
+        let movie = record(&mut emulator, recorder);
+
+        let mut faster = Chip8::with_config(PlatformConfig {
+            instructions_per_second: 1000,
+            ..PlatformConfig::default()
+        });
+        faster.load_rom(KEY_ROM.to_vec()).unwrap();
+        assert!(matches!(
+            play(&mut faster, movie.clone()),
+            Err(Chip8Error::MovieMismatch(_))
+        ));
+
+        let mut other_rom = power_on(1);
+        other_rom.load_rom(vec![0x12, 0x00]).unwrap();
+        assert!(matches!(
+            play(&mut other_rom, movie),
+            Err(Chip8Error::MovieMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn test_rejects_garbage() {
+        assert!(matches!(
+            Movie::decode(b"CH8STATE"),
+            Err(Chip8Error::InvalidMovie(_))
+        ));
+    }
+}
@@ -0,0 +1,46 @@
+//! Lookup by name for the enums with an `ALL` list, such as `Timing` or
+//! `Waveform`, so their `FromStr` impls share one body.
+
+/// The entry of `all` whose `name` is `s`, ignoring case
+pub fn find_named<T: Copy>(all: &[T], name: fn(T) -> &'static str, s: &str) -> Option<T> {
+    all.iter()
+        .copied()
+        .find(|&item| name(item).eq_ignore_ascii_case(s))
+}
+
+/// `find_named` for `FromStr`. The error lists every name, e.g.
+/// `unknown timing fast, expected one of flat, vip` when `what` is `timing`.
+pub fn parse_named<T: Copy>(
+    all: &[T],
+    name: fn(T) -> &'static str,
+    what: &str,
+    s: &str,
+) -> Result<T, String> {
+    find_named(all, name, s).ok_or_else(|| {
+        let names: Vec<&str> = all.iter().map(|&item| name(item)).collect();
+        format!(
+            "unknown {} {}, expected one of {}",
+            what,
+            s,
+            names.join(", ")
+        )
+    })
+}
+
+#[cfg(test)]
+mod named_tests {
+    use super::*;
+    use crate::timing::Timing;
+
+    #[test]
+    fn test_parse_named() {
+        assert_eq!(
+            parse_named(&Timing::ALL, Timing::name, "timing", "VIP"),
+            Ok(Timing::CosmacVip)
+        );
+        assert_eq!(
+            parse_named(&Timing::ALL, Timing::name, "timing", "fast"),
+            Err("unknown timing fast, expected one of flat, vip".to_string())
+        );
+    }
+}
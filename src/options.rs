@@ -0,0 +1,171 @@
+//! Command line options shared by `chip-8` and `chip8-headless`, so a
+//! movie recorded in the window replays headless with the same flags.
+
+use crate::{
+    audio::Buzzer,
+    platform::{Platform, PlatformConfig},
+    rng::RandomModel,
+    timing::Timing,
+};
+
+/// The machine to build: platform preset, what overrides it, the save state
+/// to start from and the buzzer
+#[derive(Debug, Clone, PartialEq)]
+pub struct MachineOptions {
+    pub platform: Option<Platform>,
+    /// Overrides the instructions per second of the platform
+    pub instructions_per_second: Option<u32>,
+    pub timing: Option<Timing>,
+    pub random_model: Option<RandomModel>,
+    /// Fixed seed for Cxkk so runs can be repeated
+    pub seed: Option<u64>,
+    /// Save state loaded right after the ROM
+    pub state: Option<String>,
+    pub buzzer: Buzzer,
+}
+
+impl MachineOptions {
+    pub fn init() -> MachineOptions {
+        MachineOptions {
+            platform: None,
+            instructions_per_second: None,
+            timing: None,
+            random_model: None,
+            seed: None,
+            state: None,
+            buzzer: Buzzer::default(),
+        }
+    }
+
+    /// Parses `arg`, taking its value from `args`, if it is one of
+    /// `--platform --ips --timing --seed --random --state --waveform
+    /// --frequency --volume`. Returns false for any other argument.
+    pub fn parse_arg(
+        &mut self,
+        arg: &str,
+        args: &mut impl Iterator<Item = String>,
+    ) -> Result<bool, String> {
+        match arg {
+            "--platform" => {
+                let name = args.next().ok_or("--platform needs a value")?;
+                self.platform = Some(name.parse()?);
+            }
+            "--ips" => {
+                let value = args.next().ok_or("--ips needs a value")?;
+                let ips = value
+                    .parse()
+                    .ok()
+                    .filter(|&ips: &u32| ips > 0)
+                    .ok_or_else(|| format!("invalid instructions per second {}", value))?;
+                self.instructions_per_second = Some(ips);
+            }
+            "--timing" => {
+                let name = args.next().ok_or("--timing needs a value")?;
+                self.timing = Some(name.parse()?);
+            }
+            "--seed" => {
+                let value = args.next().ok_or("--seed needs a value")?;
+                self.seed = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("invalid seed {}", value))?,
+                );
+            }
+            "--random" => {
+                let name = args.next().ok_or("--random needs a value")?;
+                self.random_model = Some(name.parse()?);
+            }
+            "--state" => {
+                self.state = Some(args.next().ok_or("--state needs a file name")?);
+            }
+            "--waveform" => {
+                let name = args.next().ok_or("--waveform needs a value")?;
+                self.buzzer.waveform = name.parse()?;
+            }
+            "--frequency" => {
+                let value = args.next().ok_or("--frequency needs a value")?;
+                self.buzzer.frequency = value
+                    .parse()
+                    .ok()
+                    .filter(|&hz: &f32| hz > 0.0)
+                    .ok_or_else(|| format!("invalid frequency {}", value))?;
+            }
+            "--volume" => {
+                let value = args.next().ok_or("--volume needs a value")?;
+                self.buzzer.volume = value
+                    .parse()
+                    .ok()
+                    .filter(|volume| (0.0..=1.0).contains(volume))
+                    .ok_or_else(|| format!("invalid volume {}, expected 0 to 1", value))?;
+            }
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    /// The platform preset with the overrides applied
+    pub fn config(&self) -> PlatformConfig {
+        let mut config = match self.platform {
+            Some(platform) => platform.config(),
+            None => PlatformConfig::default(),
+        };
+
+        if let Some(ips) = self.instructions_per_second {
+            config.instructions_per_second = ips;
+        }
+
+        if let Some(timing) = self.timing {
+            config.timing = timing;
+        }
+
+        if let Some(random_model) = self.random_model {
+            config.random_model = random_model;
+        }
+        config.seed = self.seed;
+
+        config
+    }
+}
+
+#[cfg(test)]
+mod options_tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<MachineOptions, String> {
+        let mut options = MachineOptions::init();
+        let mut args = args.iter().map(|arg| arg.to_string());
+
+        while let Some(arg) = args.next() {
+            if !options.parse_arg(&arg, &mut args)? {
+                return Err(format!("unexpected argument {}", arg));
+            }
+        }
+        Ok(options)
+    }
+
+    #[test]
+    fn test_config() {
+        let options =
+            parse(&["--platform", "vip", "--ips", "900", "--random", "page-sum"]).unwrap();
+        let config = options.config();
+
+        assert_eq!(
+            config.instruction_set,
+            Platform::CosmacVip.config().instruction_set
+        );
+        assert_eq!(config.instructions_per_second, 900);
+        assert_eq!(config.random_model, RandomModel::PageSum);
+        assert_eq!(config.seed, None);
+    }
+
+    #[test]
+    fn test_rejects() {
+        assert!(parse(&["--ips", "0"]).is_err());
+        assert!(parse(&["--volume"]).is_err());
+        assert_eq!(
+            parse(&["--scale", "2"]),
+            Err("unexpected argument --scale".to_string())
+        );
+    }
+}
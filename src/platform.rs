@@ -0,0 +1,120 @@
+use std::{fmt, str::FromStr};
+
+use crate::{chip8, display, named, quirks::Quirks, ram, rng::RandomModel, timing::Timing};
+
+/// Instructions understood on top of the base CHIP-8 set
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstructionSet {
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+/// Everything that differs between the machines a ROM may have been written for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlatformConfig {
+    pub quirks: Quirks,
+    pub memory_size: usize,
+    /// Full resolution of the screen. Low resolution drawing is scaled up to fill it
+    pub display_width: usize,
+    pub display_height: usize,
+    pub instructions_per_second: u32,
+    pub instruction_set: InstructionSet,
+    pub timing: Timing,
+    pub random_model: RandomModel,
+    /// Seed for Cxkk, `None` seeds from the operating system so every run differs
+    pub seed: Option<u64>,
+}
+
+impl Default for PlatformConfig {
+    fn default() -> Self {
+        PlatformConfig {
+            quirks: Quirks::default(),
+            memory_size: ram::MEMORY_SIZE,
+            display_width: display::DISPLAY_WIDTH,
+            display_height: display::DISPLAY_HEIGHT,
+            instructions_per_second: chip8::INSTRUCTIONS_PER_SECOND,
+            instruction_set: InstructionSet::Chip8,
+            timing: Timing::Flat,
+            random_model: RandomModel::SplitMix,
+            seed: None,
+        }
+    }
+}
+
+/// Named presets for the common CHIP-8 platforms
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Platform {
+    CosmacVip,
+    Chip48,
+    SuperChip11,
+    XoChip,
+}
+
+impl Platform {
+    pub const ALL: [Platform; 4] = [
+        Platform::CosmacVip,
+        Platform::Chip48,
+        Platform::SuperChip11,
+        Platform::XoChip,
+    ];
+
+    pub fn config(self) -> PlatformConfig {
+        match self {
+            Platform::CosmacVip => PlatformConfig {
+                quirks: Quirks::cosmac_vip(),
+                instructions_per_second: 600,
+                ..PlatformConfig::default()
+            },
+
+            Platform::Chip48 => PlatformConfig {
+                quirks: Quirks::superchip(),
+                instructions_per_second: 1800,
+                ..PlatformConfig::default()
+            },
+
+            Platform::SuperChip11 => PlatformConfig {
+                quirks: Quirks::superchip(),
+                display_width: display::DISPLAY_WIDTH * 2,
+                display_height: display::DISPLAY_HEIGHT * 2,
+                instructions_per_second: 1800,
+                instruction_set: InstructionSet::SuperChip,
+                ..PlatformConfig::default()
+            },
+
+            Platform::XoChip => PlatformConfig {
+                quirks: Quirks::xochip(),
+                memory_size: 0x10000,
+                display_width: display::DISPLAY_WIDTH * 2,
+                display_height: display::DISPLAY_HEIGHT * 2,
+                instructions_per_second: 60000,
+                instruction_set: InstructionSet::XoChip,
+                ..PlatformConfig::default()
+            },
+        }
+    }
+
+    /// Short name used on the command line
+    pub fn name(self) -> &'static str {
+        match self {
+            Platform::CosmacVip => "vip",
+            Platform::Chip48 => "chip48",
+            Platform::SuperChip11 => "schip",
+            Platform::XoChip => "xochip",
+        }
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Platform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        named::parse_named(&Platform::ALL, Platform::name, "platform", s)
+    }
+}
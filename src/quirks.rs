@@ -0,0 +1,49 @@
+/// Behaviour that differs between CHIP-8 interpreters.
+/// Every flag picks one of the known variants of an ambiguous instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Quirks {
+    /// 8xy6 / 8xyE shift Vy into Vx instead of shifting Vx in place
+    pub shift_uses_vy: bool,
+    /// Fx55 / Fx65 leave I pointing just past the last register they touched
+    pub load_store_increments_i: bool,
+    /// Bxnn jumps to xnn + Vx instead of nnn + V0
+    pub jump_uses_vx: bool,
+    /// 8xy1, 8xy2 and 8xy3 reset VF to zero
+    pub vf_reset: bool,
+    /// Sprites are cut off at the screen edges instead of wrapping around
+    pub clip_sprites: bool,
+    /// Dxyn waits for the next 60 Hz frame, so at most one sprite is drawn per frame
+    pub display_wait: bool,
+}
+
+impl Quirks {
+    /// Original COSMAC VIP interpreter
+    pub fn cosmac_vip() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            vf_reset: true,
+            clip_sprites: true,
+            display_wait: true,
+            ..Quirks::default()
+        }
+    }
+
+    /// CHIP-48 and SUPER-CHIP on the HP 48 calculators
+    pub fn superchip() -> Quirks {
+        Quirks {
+            jump_uses_vx: true,
+            clip_sprites: true,
+            ..Quirks::default()
+        }
+    }
+
+    /// XO-CHIP as implemented by Octo
+    pub fn xochip() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            ..Quirks::default()
+        }
+    }
+}
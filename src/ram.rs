@@ -1,3 +1,6 @@
+use crate::error::Chip8Error;
+use crate::savestate::{StateReader, StateWriter};
+
 /// storing fonts on meomory
 /// chip-8 have 16 fonts which are 5 bytes long
 /// they are used for displaying characters on screen
@@ -21,24 +24,82 @@ pub const FONT_SET: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
+/// SUPER-CHIP 8x10 digits used by Fx30, stored right after the small font.
+/// A to F come from XO-CHIP
+pub const BIG_FONTSET_START_ADDRESS: u16 = 0xA0;
+pub const BIG_FONT_SET: [u8; 160] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xE0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+pub const MEMORY_SIZE: usize = 0x1000;
+
 pub struct RAM {
-    memory: [u8; 0xFFF],
+    memory: Vec<u8>,
 }
 
 /// RAM public methods
 impl RAM {
     pub fn init() -> RAM {
-        let mut ram = RAM { memory: [0; 0xFFF] };
+        RAM::with_size(MEMORY_SIZE)
+    }
+
+    pub fn with_size(size: usize) -> RAM {
+        let mut ram = RAM {
+            memory: vec![0; size],
+        };
         ram.load_fonts();
         ram
     }
 
-    pub fn read_byte(&mut self, addr: u16) -> u8 {
-        self.memory[addr as usize]
+    pub fn read_byte(&self, addr: u16) -> Result<u8, Chip8Error> {
+        self.memory
+            .get(addr as usize)
+            .copied()
+            .ok_or(Chip8Error::MemoryFault {
+                addr: addr as usize,
+            })
     }
 
-    pub fn write_byte(&mut self, addr: u16, value: u8) {
-        self.memory[addr as usize] = value;
+    pub fn write_byte(&mut self, addr: u16, value: u8) -> Result<(), Chip8Error> {
+        match self.memory.get_mut(addr as usize) {
+            Some(byte) => {
+                *byte = value;
+                Ok(())
+            }
+            None => Err(Chip8Error::MemoryFault {
+                addr: addr as usize,
+            }),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.memory.len()
+    }
+
+    pub fn save_state(&self, out: &mut StateWriter) {
+        out.put_bytes(&self.memory);
+    }
+
+    /// Memory size is part of the platform, so it already matches
+    pub fn load_state(&mut self, input: &mut StateReader) -> Result<(), Chip8Error> {
+        let len = self.memory.len();
+        self.memory.copy_from_slice(input.get_bytes(len)?);
+        Ok(())
     }
 
     pub fn print_memory(&self) {
@@ -49,8 +110,10 @@ impl RAM {
 /// RAM private methods
 impl RAM {
     fn load_fonts(&mut self) {
-        for (i, &font) in FONT_SET.iter().enumerate() {
-            self.write_byte(FONTSET_START_ADDRESS + i as u16, font);
-        }
+        let start = FONTSET_START_ADDRESS as usize;
+        self.memory[start..start + FONT_SET.len()].copy_from_slice(&FONT_SET);
+
+        let start = BIG_FONTSET_START_ADDRESS as usize;
+        self.memory[start..start + BIG_FONT_SET.len()].copy_from_slice(&BIG_FONT_SET);
     }
 }
@@ -0,0 +1,208 @@
+use std::collections::VecDeque;
+
+use crate::{
+    chip8::{Chip8, Snapshot},
+    error::Chip8Error,
+};
+
+/// Snapshots kept by default, with `DEFAULT_INTERVAL` that is ten seconds
+pub const DEFAULT_CAPACITY: usize = 300;
+
+/// Frames between two snapshots by default
+pub const DEFAULT_INTERVAL: u32 = 2;
+
+/// Bounded history of compressed snapshots, newest last. Once full the
+/// oldest one is dropped for every new one.
+pub struct Rewind {
+    snapshots: VecDeque<Vec<u8>>,
+    capacity: usize,
+    interval: u32,
+    frames_since_snapshot: u32,
+}
+
+impl Rewind {
+    pub fn init() -> Rewind {
+        Rewind::with_capacity(DEFAULT_CAPACITY, DEFAULT_INTERVAL)
+    }
+
+    /// Keeps up to `capacity` snapshots, one every `interval` frames
+    pub fn with_capacity(capacity: usize, interval: u32) -> Rewind {
+        Rewind {
+            snapshots: VecDeque::with_capacity(capacity),
+            capacity,
+            interval: interval.max(1),
+            frames_since_snapshot: 0,
+        }
+    }
+
+    /// Called once per emulated frame, takes a snapshot every `interval` frames
+    pub fn record(&mut self, emulator: &Chip8) {
+        self.frames_since_snapshot += 1;
+        if self.frames_since_snapshot < self.interval || self.capacity == 0 {
+            return;
+        }
+        self.frames_since_snapshot = 0;
+
+        if self.snapshots.len() == self.capacity {
+            self.snapshots.pop_front();
+        }
+
+        self.snapshots
+            .push_back(compress(emulator.snapshot().as_bytes()));
+    }
+
+    /// Restores the newest snapshot that differs from the running machine
+    /// and forgets it, along with any newer ones. `record` runs right after
+    /// a frame, so the newest snapshot is usually the current state. Returns
+    /// false once there is nothing left to go back to. Keys keep their
+    /// current state.
+    pub fn step_back(&mut self, emulator: &mut Chip8) -> Result<bool, Chip8Error> {
+        let current = compress(emulator.snapshot().as_bytes());
+        let compressed = loop {
+            match self.snapshots.pop_back() {
+                Some(compressed) if compressed == current => {}
+                Some(compressed) => break compressed,
+                None => return Ok(false),
+            }
+        };
+
+        let keypad = *emulator.bus.get_keypad();
+        emulator.restore(&Snapshot::from_bytes(decompress(&compressed)?))?;
+
+        for (index, &state) in keypad.iter().enumerate() {
+            emulator.bus.handle_key_press(index, state);
+        }
+        self.frames_since_snapshot = 0;
+        Ok(true)
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+        self.frames_since_snapshot = 0;
+    }
+}
+
+// PackBits run length encoding. Memory and screen are mostly long runs of
+// zeros, so snapshots shrink a lot. A header byte n below 128 is followed by
+// n + 1 literal bytes, n above 128 by one byte repeated 257 - n times.
+fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() / 4);
+    let mut i = 0;
+
+    while i < data.len() {
+        let run = data[i..]
+            .iter()
+            .take(128)
+            .take_while(|&&byte| byte == data[i])
+            .count();
+
+        if run >= 2 {
+            out.push((257 - run) as u8);
+            out.push(data[i]);
+            i += run;
+            continue;
+        }
+
+        // literals up to the next run of at least two
+        let start = i;
+        while i < data.len() && i - start < 128 {
+            if i + 1 < data.len() && data[i] == data[i + 1] {
+                break;
+            }
+            i += 1;
+        }
+
+        out.push((i - start - 1) as u8);
+        out.extend_from_slice(&data[start..i]);
+    }
+
+    out
+}
+
+fn decompress(data: &[u8]) -> Result<Vec<u8>, Chip8Error> {
+    let corrupt = || Chip8Error::InvalidSaveState("corrupt rewind snapshot");
+    let mut out = Vec::new();
+    let mut i = 0;
+
+    while i < data.len() {
+        let header = data[i] as usize;
+        i += 1;
+
+        if header < 128 {
+            let literal = data.get(i..i + header + 1).ok_or_else(corrupt)?;
+            out.extend_from_slice(literal);
+            i += header + 1;
+        } else if header > 128 {
+            let &byte = data.get(i).ok_or_else(corrupt)?;
+            out.resize(out.len() + 257 - header, byte);
+            i += 1;
+        } else {
+            return Err(corrupt());
+        }
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod rewind_tests {
+    use super::*;
+
+    #[test]
+    fn test_compress_roundtrip() {
+        let mut data = vec![0; 1000];
+        data.extend([1, 2, 3, 3, 4]);
+        data.extend((0..=255).collect::<Vec<u8>>());
+        data.push(7);
+
+        let compressed = compress(&data);
+        assert!(compressed.len() < data.len());
+        assert_eq!(decompress(&compressed).unwrap(), data);
+
+        assert!(compress(&[]).is_empty());
+        assert_eq!(decompress(&compress(&[9])).unwrap(), vec![9]);
+    }
+
+    #[test]
+    fn test_step_back_in_reverse() {
+        let mut emulator = Chip8::init();
+        // V0 = V0 + 1, jump back
+        emulator.load_rom(vec![0x70, 0x01, 0x12, 0x00]).unwrap();
+        emulator.config.instructions_per_second = 120;
+
+        let mut rewind = Rewind::with_capacity(3, 1);
+        for _ in 0..5 {
+            emulator.run_frame().unwrap();
+            rewind.record(&emulator);
+        }
+        assert_eq!(rewind.len(), 3);
+        assert_eq!(emulator.cpu.get_vreg_value(0), 5);
+
+        let mut seen = Vec::new();
+        while rewind.step_back(&mut emulator).unwrap() {
+            seen.push(emulator.cpu.get_vreg_value(0));
+        }
+        // the newest snapshot is the state the emulator is already in
+        assert_eq!(seen, vec![4, 3]);
+        assert!(rewind.is_empty());
+    }
+
+    #[test]
+    fn test_interval() {
+        let emulator = Chip8::init();
+        let mut rewind = Rewind::with_capacity(10, 4);
+
+        for _ in 0..9 {
+            rewind.record(&emulator);
+        }
+        assert_eq!(rewind.len(), 2);
+    }
+}
@@ -0,0 +1,95 @@
+use std::{fmt, str::FromStr};
+
+use crate::named;
+
+/// How Cxkk comes up with its random byte
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RandomModel {
+    /// Well mixed 64 bit generator
+    SplitMix,
+    /// Loosely after the COSMAC VIP interpreter, which summed the bytes of
+    /// its own code: a byte pointer walks the first page of memory and every
+    /// byte it passes is added to the previous result. That page holds the
+    /// fonts and zeros here, not the VIP interpreter, so the numbers are
+    /// only as cheap and streaky as the original's, not the same ones.
+    PageSum,
+}
+
+impl RandomModel {
+    pub const ALL: [RandomModel; 2] = [RandomModel::SplitMix, RandomModel::PageSum];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RandomModel::SplitMix => "splitmix",
+            RandomModel::PageSum => "page-sum",
+        }
+    }
+}
+
+impl fmt::Display for RandomModel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for RandomModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        named::parse_named(&RandomModel::ALL, RandomModel::name, "random model", s)
+    }
+}
+
+/// Random number generator behind Cxkk, `next_byte` for
+/// `RandomModel::SplitMix` and `next_page_sum_byte` for `RandomModel::PageSum`.
+/// Owned by the machine so its state can go into save states.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn from_seed(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    /// Seeded from the operating system
+    pub fn from_entropy() -> Rng {
+        Rng::from_seed(rand::random())
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    pub fn next_byte(&mut self) -> u8 {
+        (self.next_u64() >> 56) as u8
+    }
+
+    /// Next byte of the `RandomModel::PageSum` routine. The low byte of
+    /// the state is the pointer into the first memory page, `table` reads
+    /// the byte at it, the second byte of the state is the last result.
+    pub fn next_page_sum_byte<E>(
+        &mut self,
+        table: impl FnOnce(u8) -> Result<u8, E>,
+    ) -> Result<u8, E> {
+        let pointer = (self.state as u8).wrapping_add(1);
+        let value = ((self.state >> 8) as u8).wrapping_add(table(pointer)?);
+
+        self.state = (self.state & !0xFFFF) | u64::from(value) << 8 | u64::from(pointer);
+        Ok(value)
+    }
+
+    pub fn get_state(&self) -> u64 {
+        self.state
+    }
+
+    pub fn set_state(&mut self, state: u64) {
+        self.state = state;
+    }
+}
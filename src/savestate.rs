@@ -0,0 +1,188 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    error::Chip8Error,
+    platform::{InstructionSet, PlatformConfig},
+    rng::RandomModel,
+};
+
+/// First bytes of every save state file
+pub const MAGIC: &[u8; 8] = b"CH8STATE";
+
+/// Bumped whenever the layout changes, older files are rejected
+pub const VERSION: u16 = 6;
+
+/// Little endian byte sink the components write their state into
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn init() -> StateWriter {
+        StateWriter { data: Vec::new() }
+    }
+
+    pub fn put_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn put_bool(&mut self, value: bool) {
+        self.put_u8(value as u8);
+    }
+
+    pub fn put_u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn put_u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn put_u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn put_bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+/// Reads back what a `StateWriter` wrote. Running out of data is an error,
+/// never a panic.
+pub struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub fn init(data: &'a [u8]) -> StateReader<'a> {
+        StateReader { data }
+    }
+
+    pub fn get_bytes(&mut self, len: usize) -> Result<&'a [u8], Chip8Error> {
+        if self.data.len() < len {
+            return Err(Chip8Error::InvalidSaveState("file is truncated"));
+        }
+
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    pub fn get_u8(&mut self) -> Result<u8, Chip8Error> {
+        Ok(self.get_bytes(1)?[0])
+    }
+
+    pub fn get_bool(&mut self) -> Result<bool, Chip8Error> {
+        match self.get_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(Chip8Error::InvalidSaveState("bad flag value")),
+        }
+    }
+
+    pub fn get_u16(&mut self) -> Result<u16, Chip8Error> {
+        let bytes = self.get_bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn get_u32(&mut self) -> Result<u32, Chip8Error> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.get_bytes(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn get_u64(&mut self) -> Result<u64, Chip8Error> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.get_bytes(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    /// Fails if anything is left over
+    pub fn finish(self) -> Result<(), Chip8Error> {
+        if self.data.is_empty() {
+            Ok(())
+        } else {
+            Err(Chip8Error::InvalidSaveState("unexpected data at the end"))
+        }
+    }
+}
+
+/// Writes the magic, version and the platform the state belongs to
+pub fn write_header(out: &mut StateWriter, config: &PlatformConfig) {
+    out.put_bytes(MAGIC);
+    out.put_u16(VERSION);
+    write_platform(out, config);
+}
+
+/// Checks the magic, version and platform written by `write_header`
+pub fn check_header(input: &mut StateReader, config: &PlatformConfig) -> Result<(), Chip8Error> {
+    if input.get_bytes(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+        return Err(Chip8Error::InvalidSaveState("not a save state"));
+    }
+
+    let version = input.get_u16()?;
+    if version != VERSION {
+        return Err(Chip8Error::SaveStateVersion {
+            found: version,
+            expected: VERSION,
+        });
+    }
+
+    let mut expected = StateWriter::init();
+    write_platform(&mut expected, config);
+    let expected = expected.into_bytes();
+
+    if input.get_bytes(expected.len())? != expected.as_slice() {
+        return Err(Chip8Error::SaveStatePlatform);
+    }
+
+    Ok(())
+}
+
+/// Everything that decides whether the machine state fits: memory and
+/// screen size, the instruction set, the quirks and how the saved random
+/// state is read
+pub fn write_platform(out: &mut StateWriter, config: &PlatformConfig) {
+    let instruction_set = match config.instruction_set {
+        InstructionSet::Chip8 => 0,
+        InstructionSet::SuperChip => 1,
+        InstructionSet::XoChip => 2,
+    };
+
+    let quirks = &config.quirks;
+    let quirk_bits = [
+        quirks.shift_uses_vy,
+        quirks.load_store_increments_i,
+        quirks.jump_uses_vx,
+        quirks.vf_reset,
+        quirks.clip_sprites,
+        quirks.display_wait,
+    ]
+    .iter()
+    .enumerate()
+    .fold(0u8, |bits, (i, &on)| bits | (on as u8) << i);
+
+    let random_model = match config.random_model {
+        RandomModel::SplitMix => 0,
+        RandomModel::PageSum => 1,
+    };
+
+    out.put_u8(instruction_set);
+    out.put_u32(config.memory_size as u32);
+    out.put_u16(config.display_width as u16);
+    out.put_u16(config.display_height as u16);
+    out.put_u8(quirk_bits);
+    out.put_u8(random_model);
+}
+
+/// File for save `slot` of the ROM at `rom`, next to the ROM: `pong.ch8`
+/// saves to `pong.ch8.state1`
+pub fn slot_path<P: AsRef<Path>>(rom: P, slot: usize) -> PathBuf {
+    let mut path = rom.as_ref().as_os_str().to_owned();
+    path.push(format!(".state{}", slot));
+    PathBuf::from(path)
+}
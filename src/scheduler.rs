@@ -0,0 +1,75 @@
+use std::time::Duration;
+
+pub const TIMER_HZ: u32 = 60;
+
+// time is counted in 1/60 ns so a 60 Hz frame is exactly this many units
+const FRAME_UNITS: u128 = 1_000_000_000;
+
+// after a long stall at most this many frames are caught up, the rest is dropped
+const MAX_CATCH_UP_FRAMES: u128 = 15;
+
+/// Fixed timestep clock. Real time goes into an accumulator and comes out
+/// as whole 60 Hz frames, so the timers tick at exactly 60 Hz however long
+/// each frame took to emulate and display.
+pub struct Scheduler {
+    // real time not yet emulated
+    accumulator: u128,
+}
+
+impl Scheduler {
+    pub fn init() -> Scheduler {
+        Scheduler { accumulator: 0 }
+    }
+
+    /// Adds `elapsed` real time to the clock and returns how many frames
+    /// are now due
+    pub fn frames_due(&mut self, elapsed: Duration) -> u32 {
+        self.accumulator += elapsed.as_nanos() * u128::from(TIMER_HZ);
+        self.accumulator = self
+            .accumulator
+            .min(FRAME_UNITS * MAX_CATCH_UP_FRAMES + FRAME_UNITS - 1);
+
+        let frames = self.accumulator / FRAME_UNITS;
+        self.accumulator %= FRAME_UNITS;
+
+        frames as u32
+    }
+
+    /// Real time left until the next frame is due
+    pub fn time_until_next_frame(&self) -> Duration {
+        let units = FRAME_UNITS - self.accumulator;
+        Duration::from_nanos((units / u128::from(TIMER_HZ)) as u64)
+    }
+
+    /// Forgets the time that has not been emulated yet, e.g. after a pause
+    pub fn reset(&mut self) {
+        self.accumulator = 0;
+    }
+}
+
+#[cfg(test)]
+mod scheduler_tests {
+    use super::*;
+
+    const FRAME: Duration = Duration::from_nanos(16_666_667);
+
+    #[test]
+    fn test_frames_due() {
+        let mut scheduler = Scheduler::init();
+
+        assert_eq!(scheduler.frames_due(FRAME / 2), 0);
+        assert_eq!(scheduler.frames_due(FRAME - FRAME / 2), 1);
+        assert_eq!(scheduler.frames_due(FRAME * 10), 10);
+        assert_eq!(scheduler.frames_due(Duration::from_secs(10)), 15);
+    }
+
+    #[test]
+    fn test_reset_drops_pending_time() {
+        let mut scheduler = Scheduler::init();
+
+        scheduler.frames_due(FRAME * 9 / 10);
+        scheduler.reset();
+        assert_eq!(scheduler.frames_due(FRAME / 2), 0);
+        assert!(scheduler.time_until_next_frame() < FRAME);
+    }
+}
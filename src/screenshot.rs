@@ -0,0 +1,260 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    iter,
+    path::{Path, PathBuf},
+};
+
+use crate::display::{self, Display};
+use crate::error::Chip8Error;
+use crate::named;
+
+/// File formats a screenshot can be written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// Colour image using the palette
+    Png,
+    /// Black and white binary PBM, every lit pixel is black whatever its plane
+    Pbm,
+    /// Same characters as `Display::to_text`
+    Text,
+}
+
+impl ImageFormat {
+    pub const ALL: [ImageFormat; 3] = [ImageFormat::Png, ImageFormat::Pbm, ImageFormat::Text];
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Pbm => "pbm",
+            ImageFormat::Text => "txt",
+        }
+    }
+
+    /// Picks the format from the extension of `path`
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<ImageFormat> {
+        let extension = path.as_ref().extension()?.to_str()?;
+
+        named::find_named(&ImageFormat::ALL, ImageFormat::extension, extension)
+    }
+}
+
+/// Writes the display buffer out as an image. Every screen pixel becomes a
+/// `scale` by `scale` block, so a scale of 1 keeps the native resolution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Screenshot {
+    /// RGBA colour of each pixel value, alpha is ignored
+    pub palette: [u32; 4],
+    pub scale: usize,
+}
+
+impl Default for Screenshot {
+    fn default() -> Self {
+        Screenshot {
+            palette: display::PALETTE,
+            scale: 1,
+        }
+    }
+}
+
+impl Screenshot {
+    pub fn init() -> Screenshot {
+        Screenshot::default()
+    }
+
+    pub fn with_scale(scale: usize) -> Screenshot {
+        Screenshot {
+            scale: scale.max(1),
+            ..Screenshot::default()
+        }
+    }
+
+    /// Scale that makes the image as big as the SDL window
+    pub fn window_scale(display: &Display) -> usize {
+        display::DISPLAY_WIDTH * display::SCALE_FACTOR as usize / display.width()
+    }
+
+    /// Size of the image written for `display`
+    pub fn size(&self, display: &Display) -> (usize, usize) {
+        (display.width() * self.scale, display.height() * self.scale)
+    }
+
+    pub fn write_png<W: Write>(&self, display: &Display, out: W) -> io::Result<()> {
+        let (width, height) = self.size(display);
+
+        let mut encoder = png::Encoder::new(out, width as u32, height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut data = Vec::with_capacity(width * height * 3);
+        for row in self.scaled_rows(display) {
+            for pixel in row {
+                let color = self.palette[(pixel & 0b11) as usize];
+                data.extend_from_slice(&color.to_be_bytes()[..3]);
+            }
+        }
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&data)?;
+        writer.finish()?;
+        Ok(())
+    }
+
+    pub fn write_pbm<W: Write>(&self, display: &Display, mut out: W) -> io::Result<()> {
+        let (width, height) = self.size(display);
+        write!(out, "P4\n{} {}\n", width, height)?;
+
+        // rows are padded to whole bytes, first pixel in the high bit
+        let mut line = vec![0u8; width.div_ceil(8)];
+        for row in self.scaled_rows(display) {
+            line.fill(0);
+
+            for (x, pixel) in row.enumerate() {
+                if pixel != 0 {
+                    line[x / 8] |= 0x80 >> (x % 8);
+                }
+            }
+
+            out.write_all(&line)?;
+        }
+
+        out.flush()
+    }
+
+    pub fn write_text<W: Write>(&self, display: &Display, mut out: W) -> io::Result<()> {
+        for row in self.scaled_rows(display) {
+            let line: String = row.map(display::pixel_char).collect();
+            writeln!(out, "{}", line)?;
+        }
+
+        out.flush()
+    }
+
+    pub fn write<W: Write>(
+        &self,
+        display: &Display,
+        format: ImageFormat,
+        out: W,
+    ) -> io::Result<()> {
+        match format {
+            ImageFormat::Png => self.write_png(display, out),
+            ImageFormat::Pbm => self.write_pbm(display, out),
+            ImageFormat::Text => self.write_text(display, out),
+        }
+    }
+
+    /// Saves to `path` in the format its extension names
+    pub fn save<P: AsRef<Path>>(&self, display: &Display, path: P) -> Result<(), Chip8Error> {
+        let path = path.as_ref();
+        let format = ImageFormat::from_path(path).ok_or_else(|| {
+            Chip8Error::Output(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not a .png, .pbm or .txt file", path.display()),
+            ))
+        })?;
+
+        let file = File::create(path).map_err(Chip8Error::Output)?;
+        self.write(display, format, BufWriter::new(file))
+            .map_err(Chip8Error::Output)
+    }
+
+    // every row of the display repeated `scale` times, with every pixel repeated `scale` times
+    pub(crate) fn scaled_rows<'a>(
+        &'a self,
+        display: &'a Display,
+    ) -> impl Iterator<Item = impl Iterator<Item = u32> + 'a> + 'a {
+        let scale = self.scale;
+
+        display
+            .get_buffer()
+            .chunks(display.width())
+            .flat_map(move |row| iter::repeat_n(row, scale))
+            .map(move |row| {
+                row.iter()
+                    .flat_map(move |&pixel| iter::repeat_n(pixel, scale))
+            })
+    }
+}
+
+/// First `pong.ch8.<kind>N.<extension>` next to the ROM that does not exist
+/// yet, or `pong.ch8.<kind>N` when `extension` is empty
+pub fn next_path<P: AsRef<Path>>(rom: P, kind: &str, extension: &str) -> PathBuf {
+    (1..)
+        .map(|n| {
+            let mut path = rom.as_ref().as_os_str().to_owned();
+            path.push(format!(".{}{}", kind, n));
+            if !extension.is_empty() {
+                path.push(format!(".{}", extension));
+            }
+            PathBuf::from(path)
+        })
+        .find(|path| fs::metadata(path).is_err())
+        .unwrap()
+}
+
+#[cfg(test)]
+mod screenshot_tests {
+    use super::*;
+
+    fn checker() -> Display {
+        let mut display = Display::with_size(4, 2);
+        display.write_pixel(0, 1);
+        display.write_pixel(3, 2);
+        display.write_pixel(5, 3);
+        display
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(ImageFormat::from_path("a/b.PNG"), Some(ImageFormat::Png));
+        assert_eq!(ImageFormat::from_path("b.pbm"), Some(ImageFormat::Pbm));
+        assert_eq!(ImageFormat::from_path("b.txt"), Some(ImageFormat::Text));
+        assert_eq!(ImageFormat::from_path("b.jpg"), None);
+        assert_eq!(ImageFormat::from_path("b"), None);
+    }
+
+    #[test]
+    fn test_text_scaled() {
+        let mut out = Vec::new();
+        Screenshot::with_scale(2)
+            .write_text(&checker(), &mut out)
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "##....++\n##....++\n..**....\n..**....\n"
+        );
+    }
+
+    #[test]
+    fn test_pbm() {
+        let mut out = Vec::new();
+        Screenshot::init().write_pbm(&checker(), &mut out).unwrap();
+
+        assert_eq!(out, b"P4\n4 2\n\x90\x40");
+    }
+
+    #[test]
+    fn test_png_palette() {
+        let mut out = Vec::new();
+        Screenshot::with_scale(3)
+            .write_png(&checker(), &mut out)
+            .unwrap();
+
+        let mut reader = png::Decoder::new(out.as_slice()).read_info().unwrap();
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data).unwrap();
+        assert_eq!((info.width, info.height), (12, 6));
+
+        // second row of the scaled first pixel, then the plane 2 pixel
+        assert_eq!(&data[12 * 3..12 * 3 + 3], &[0xFF, 0xFF, 0xFF]);
+        assert_eq!(&data[9 * 3..9 * 3 + 3], &[0xFF, 0x66, 0x00]);
+        assert_eq!(&data[3 * 3..3 * 3 + 3], &[0, 0, 0]);
+    }
+
+    #[test]
+    fn test_window_scale() {
+        assert_eq!(Screenshot::window_scale(&Display::init()), 14);
+        assert_eq!(Screenshot::window_scale(&Display::with_size(128, 64)), 7);
+    }
+}
@@ -1,10 +1,13 @@
-use crate::{
-    chip8::{self, Chip8, EmulatorState},
+use chip_8::{
+    audio::{self, Audio, Buzzer, SampleGenerator},
+    capture::CaptureFormat,
     display,
+    frontend::{AudioSink, InputEvent, InputSource, VideoSink},
 };
 use sdl2::{
-    event::{self, Event},
-    keyboard::Keycode,
+    audio::{AudioQueue, AudioSpecDesired},
+    event::Event,
+    keyboard::{Keycode, Mod},
     pixels::Color,
     rect::Rect,
     render::Canvas,
@@ -15,10 +18,17 @@ use sdl2::{
 pub struct SDLHandler {
     pub sdl: Sdl,
     pub canvas: Canvas<Window>,
+    // None when no audio device could be opened
+    audio_queue: Option<AudioQueue<f32>>,
+    generator: SampleGenerator,
+    muted: bool,
 }
 
+// queued audio is capped to a few frames so the sound stays in sync with the picture
+const MAX_QUEUED_FRAMES: u32 = 4;
+
 impl SDLHandler {
-    pub fn init() -> SDLHandler {
+    pub fn init(buzzer: Buzzer) -> SDLHandler {
         let mut sdl_context = sdl2::init().expect("Failed to initialize sdl");
         let video_subsystem = sdl_context
             .video()
@@ -39,9 +49,20 @@ impl SDLHandler {
             .build()
             .expect("Failed to build canvas");
 
+        let audio_queue = match open_audio_queue(&sdl_context) {
+            Ok(queue) => Some(queue),
+            Err(err) => {
+                eprintln!("Failed to open audio device, sound is off: {}", err);
+                None
+            }
+        };
+
         let mut handler = SDLHandler {
             canvas,
             sdl: sdl_context,
+            audio_queue,
+            generator: SampleGenerator::with_buzzer(audio::SAMPLE_RATE, buzzer),
+            muted: false,
         };
 
         // Initial screen to background colour
@@ -50,130 +71,194 @@ impl SDLHandler {
         handler
     }
 
-    // CHIP8 keypad    QWERTY
-    // 123C            1234
-    // 456D            qwer
-    // 789E            asdf
-    // A0BF            zxcv
-    pub fn handle_events(&self, emulator: &mut Chip8) {
+    pub fn clear_screen(&mut self) {
+        let r: u8 = ((display::BG_COLOR >> 24) & 0xFF) as u8;
+        let g: u8 = ((display::BG_COLOR >> 16) & 0xFF) as u8;
+        let b: u8 = ((display::BG_COLOR >> 8) & 0xFF) as u8;
+        let a: u8 = ((display::BG_COLOR >> 24) & 0xFF) as u8;
+
+        let color = Color::RGBA(r, g, b, a);
+        self.canvas.set_draw_color(color);
+        self.canvas.clear();
+        self.canvas.present();
+    }
+
+    pub fn toggle_mute(&mut self) {
+        self.muted = !self.muted;
+
+        if self.muted {
+            println!("=== MUTED ====")
+        } else {
+            println!("=== UNMUTED ====")
+        }
+    }
+}
+
+fn open_audio_queue(sdl: &Sdl) -> Result<AudioQueue<f32>, String> {
+    let audio_subsystem = sdl.audio()?;
+
+    let spec = AudioSpecDesired {
+        freq: Some(audio::SAMPLE_RATE as i32),
+        channels: Some(1),
+        samples: None,
+    };
+
+    let queue = audio_subsystem.open_queue::<f32, _>(None, &spec)?;
+    queue.resume();
+
+    Ok(queue)
+}
+
+// CHIP8 keypad    QWERTY
+// 123C            1234
+// 456D            qwer
+// 789E            asdf
+// A0BF            zxcv
+fn keypad_index(key: Keycode) -> Option<usize> {
+    match key {
+        Keycode::Num1 => Some(0x1),
+        Keycode::Num2 => Some(0x2),
+        Keycode::Num3 => Some(0x3),
+        Keycode::Num4 => Some(0xC),
+
+        Keycode::Q => Some(0x4),
+        Keycode::W => Some(0x5),
+        Keycode::E => Some(0x6),
+        Keycode::R => Some(0xD),
+
+        Keycode::A => Some(0x7),
+        Keycode::S => Some(0x8),
+        Keycode::D => Some(0x9),
+        Keycode::F => Some(0xE),
+
+        Keycode::Z => Some(0xA),
+        Keycode::X => Some(0x0),
+        Keycode::C => Some(0xB),
+        Keycode::V => Some(0xF),
+
+        _ => None,
+    }
+}
+
+impl SDLHandler {
+    // turns one SDL event into keypad and control events
+    fn handle_event(&mut self, event: Event, events: &mut Vec<InputEvent>) {
+        match event {
+            Event::Quit { .. } => events.push(InputEvent::Quit),
+
+            Event::KeyUp {
+                keycode: Some(key), ..
+            } => match key {
+                Keycode::Backspace => events.push(InputEvent::Rewind(false)),
+
+                _ => {
+                    if let Some(index) = keypad_index(key) {
+                        events.push(InputEvent::Key(index, false));
+                    }
+                }
+            },
+
+            Event::KeyDown {
+                keycode: Some(key),
+                keymod,
+                ..
+            } => match key {
+                Keycode::Escape => events.push(InputEvent::Quit),
+                Keycode::Space => events.push(InputEvent::TogglePause),
+                Keycode::M => self.toggle_mute(),
+                Keycode::Backspace => events.push(InputEvent::Rewind(true)),
+
+                // F1-F4 save to slots 1-4, F5-F8 load them
+                Keycode::F1 => events.push(InputEvent::SaveState(1)),
+                Keycode::F2 => events.push(InputEvent::SaveState(2)),
+                Keycode::F3 => events.push(InputEvent::SaveState(3)),
+                Keycode::F4 => events.push(InputEvent::SaveState(4)),
+                Keycode::F5 => events.push(InputEvent::LoadState(1)),
+                Keycode::F6 => events.push(InputEvent::LoadState(2)),
+                Keycode::F7 => events.push(InputEvent::LoadState(3)),
+                Keycode::F8 => events.push(InputEvent::LoadState(4)),
+                Keycode::F9 => events.push(InputEvent::Debug),
+                Keycode::F12 => events.push(InputEvent::Screenshot),
+
+                // F11 records a GIF, Shift+F11 a directory of PNGs
+                Keycode::F11 if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) => {
+                    events.push(InputEvent::ToggleCapture(CaptureFormat::PngSequence))
+                }
+                Keycode::F11 => events.push(InputEvent::ToggleCapture(CaptureFormat::Gif)),
+
+                _ => {
+                    if let Some(index) = keypad_index(key) {
+                        events.push(InputEvent::Key(index, true));
+                    }
+                }
+            },
+
+            _ => {}
+        }
+    }
+}
+
+impl InputSource for SDLHandler {
+    fn poll_events(&mut self) -> Vec<InputEvent> {
         let mut event_pump = self
             .sdl
             .event_pump()
             .expect("failed to initialize event pump");
 
-        for event in event_pump.poll_iter() {
-            match event {
-                Event::Quit { .. } => emulator.change_state(EmulatorState::Quit),
-
-                Event::KeyUp {
-                    keycode: Some(key), ..
-                } => match key {
-                    Keycode::Num1 => emulator.bus.handle_key_press(0x1, false),
-                    Keycode::Num2 => emulator.bus.handle_key_press(0x2, false),
-                    Keycode::Num3 => emulator.bus.handle_key_press(0x3, false),
-                    Keycode::Num4 => emulator.bus.handle_key_press(0xC, false),
-
-                    Keycode::Q => emulator.bus.handle_key_press(0x4, false),
-                    Keycode::W => emulator.bus.handle_key_press(0x5, false),
-                    Keycode::E => emulator.bus.handle_key_press(0x6, false),
-                    Keycode::R => emulator.bus.handle_key_press(0xD, false),
-
-                    Keycode::A => emulator.bus.handle_key_press(0x7, false),
-                    Keycode::S => emulator.bus.handle_key_press(0x8, false),
-                    Keycode::D => emulator.bus.handle_key_press(0x9, false),
-                    Keycode::F => emulator.bus.handle_key_press(0xE, false),
-
-                    Keycode::Z => emulator.bus.handle_key_press(0xA, false),
-                    Keycode::X => emulator.bus.handle_key_press(0x0, false),
-                    Keycode::C => emulator.bus.handle_key_press(0xB, false),
-                    Keycode::V => emulator.bus.handle_key_press(0xF, false),
-
-                    _ => {}
-                },
-
-                Event::KeyDown {
-                    keycode: Some(key), ..
-                } => match key {
-                    Keycode::Escape => emulator.change_state(EmulatorState::Quit),
-
-                    Keycode::Space => {
-                        if emulator.state == EmulatorState::Running {
-                            emulator.change_state(EmulatorState::PAUSED);
-                            println!("=== PAUSED ====")
-                        } else {
-                            emulator.change_state(EmulatorState::Running);
-                            println!("=== RUNNING ====")
-                        }
-                    }
+        let mut events = Vec::new();
 
-                    Keycode::Num1 => emulator.bus.handle_key_press(0x1, true),
-                    Keycode::Num2 => emulator.bus.handle_key_press(0x2, true),
-                    Keycode::Num3 => emulator.bus.handle_key_press(0x3, true),
-                    Keycode::Num4 => emulator.bus.handle_key_press(0xC, true),
+        for event in event_pump.poll_iter() {
+            self.handle_event(event, &mut events);
+        }
 
-                    Keycode::Q => emulator.bus.handle_key_press(0x4, true),
-                    Keycode::W => emulator.bus.handle_key_press(0x5, true),
-                    Keycode::E => emulator.bus.handle_key_press(0x6, true),
-                    Keycode::R => emulator.bus.handle_key_press(0xD, true),
+        events
+    }
 
-                    Keycode::A => emulator.bus.handle_key_press(0x7, true),
-                    Keycode::S => emulator.bus.handle_key_press(0x8, true),
-                    Keycode::D => emulator.bus.handle_key_press(0x9, true),
-                    Keycode::F => emulator.bus.handle_key_press(0xE, true),
+    fn wait_events(&mut self) -> Vec<InputEvent> {
+        let mut event_pump = self
+            .sdl
+            .event_pump()
+            .expect("failed to initialize event pump");
 
-                    Keycode::Z => emulator.bus.handle_key_press(0xA, true),
-                    Keycode::X => emulator.bus.handle_key_press(0x0, true),
-                    Keycode::C => emulator.bus.handle_key_press(0xB, true),
-                    Keycode::V => emulator.bus.handle_key_press(0xF, true),
+        let mut events = Vec::new();
 
-                    _ => {}
-                },
+        // window events like focus changes do not count, keep sleeping
+        while events.is_empty() {
+            let event = event_pump.wait_event();
+            self.handle_event(event, &mut events);
 
-                _ => {}
+            for event in event_pump.poll_iter() {
+                self.handle_event(event, &mut events);
             }
         }
-    }
-
-    pub fn clear_screen(&mut self) {
-        let r: u8 = ((display::BG_COLOR >> 24) & 0xFF) as u8;
-        let g: u8 = ((display::BG_COLOR >> 16) & 0xFF) as u8;
-        let b: u8 = ((display::BG_COLOR >> 8) & 0xFF) as u8;
-        let a: u8 = ((display::BG_COLOR >> 24) & 0xFF) as u8;
 
-        let color = Color::RGBA(r, g, b, a);
-        self.canvas.set_draw_color(color);
-        self.canvas.clear();
-        self.canvas.present();
+        events
     }
+}
 
-    pub fn update_screen(&mut self, emulator: &mut Chip8) {
-        let bg_r: u8 = ((display::BG_COLOR >> 24) & 0xFF) as u8;
-        let bg_g: u8 = ((display::BG_COLOR >> 16) & 0xFF) as u8;
-        let bg_b: u8 = ((display::BG_COLOR >> 8) & 0xFF) as u8;
-        let bg_a: u8 = (display::BG_COLOR & 0xFF) as u8;
-
-        let fg_r: u8 = ((display::FG_COLOR >> 24) & 0xFF) as u8;
-        let fg_g: u8 = ((display::FG_COLOR >> 16) & 0xFF) as u8;
-        let fg_b: u8 = ((display::FG_COLOR >> 8) & 0xFF) as u8;
-        let fg_a: u8 = (display::FG_COLOR & 0xFF) as u8;
+impl VideoSink for SDLHandler {
+    fn present(&mut self, buffer: &[u32], width: usize, _height: usize) {
+        // the window keeps its size, bigger screens get smaller pixels
+        let pixel_size = display::DISPLAY_WIDTH as u32 * display::SCALE_FACTOR / width as u32;
 
-        let fg_color = Color::RGBA(fg_r, fg_g, fg_b, fg_a);
-        let bg_color = Color::RGBA(bg_r, bg_g, bg_b, bg_a);
+        // one colour per combination of lit XO-CHIP planes
+        let palette = display::PALETTE.map(|color| {
+            let r: u8 = ((color >> 24) & 0xFF) as u8;
+            let g: u8 = ((color >> 16) & 0xFF) as u8;
+            let b: u8 = ((color >> 8) & 0xFF) as u8;
+            let a: u8 = (color & 0xFF) as u8;
 
-        let video_buffer = emulator.get_video_buffer();
+            Color::RGBA(r, g, b, a)
+        });
 
-        for i in 0..video_buffer.len() {
+        for (i, &pixel) in buffer.iter().enumerate() {
             // extracting x and y coords with correct scale factor
-            let x = (i % display::DISPLAY_WIDTH) as i32 * display::SCALE_FACTOR as i32;
-            let y = (i / display::DISPLAY_WIDTH) as i32 * display::SCALE_FACTOR as i32;
+            let x = (i % width) as i32 * pixel_size as i32;
+            let y = (i / width) as i32 * pixel_size as i32;
 
-            let rect = Rect::new(x, y, display::SCALE_FACTOR, display::SCALE_FACTOR);
+            let rect = Rect::new(x, y, pixel_size, pixel_size);
 
-            if video_buffer[i] == 1 {
-                self.canvas.set_draw_color(fg_color);
-            } else {
-                self.canvas.set_draw_color(bg_color);
-            }
+            self.canvas.set_draw_color(palette[(pixel & 0b11) as usize]);
 
             self.canvas
                 .fill_rect(rect)
@@ -183,3 +268,23 @@ impl SDLHandler {
         self.canvas.present();
     }
 }
+
+impl AudioSink for SDLHandler {
+    fn play_frame(&mut self, audio: &Audio, playing: bool) {
+        let samples = self.generator.generate_frame(audio, playing && !self.muted);
+
+        let queue = match &self.audio_queue {
+            Some(queue) => queue,
+            None => return,
+        };
+
+        let frame_bytes = (samples.len() * std::mem::size_of::<f32>()) as u32;
+        if queue.size() > MAX_QUEUED_FRAMES * frame_bytes {
+            return;
+        }
+
+        if let Err(err) = queue.queue_audio(&samples) {
+            eprintln!("Failed to queue audio: {}", err);
+        }
+    }
+}
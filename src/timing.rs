@@ -0,0 +1,121 @@
+use std::{fmt, str::FromStr};
+
+use crate::{cpu::CPU, named};
+
+/// Machine cycles of the COSMAC VIP's 1802 in one 60 Hz frame
+/// (1.7609 MHz, 8 clocks per machine cycle)
+pub const VIP_CYCLES_PER_FRAME: u32 = 3668;
+
+/// Cycles taken each frame by the 1861 video chip's DMA and the interrupt
+/// routine that feeds it and decrements the timers
+pub const VIP_DISPLAY_CYCLES: u32 = 1024 + 46;
+
+/// Cycles left for the interpreter in every frame
+pub const VIP_INTERPRETER_CYCLES: u32 = VIP_CYCLES_PER_FRAME - VIP_DISPLAY_CYCLES;
+
+// fetch and dispatch cost shared by every instruction
+const VIP_FETCH_CYCLES: u32 = 40;
+
+/// How the time an instruction takes is counted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timing {
+    /// Every instruction takes the same time, the platform's
+    /// `instructions_per_second` are spread evenly over the frames
+    Flat,
+    /// Every instruction costs its COSMAC VIP machine cycles and Dxyn
+    /// waits for the vertical blank
+    CosmacVip,
+}
+
+impl Timing {
+    pub const ALL: [Timing; 2] = [Timing::Flat, Timing::CosmacVip];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Timing::Flat => "flat",
+            Timing::CosmacVip => "vip",
+        }
+    }
+}
+
+impl fmt::Display for Timing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Timing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        named::parse_named(&Timing::ALL, Timing::name, "timing", s)
+    }
+}
+
+/// Machine cycles the original VIP interpreter spends on `opcode`, given the
+/// registers before it runs. The figures follow published analyses of the
+/// interpreter listing: data dependent costs such as sprite alignment and
+/// the BCD loop are modelled, branch-internal jitter of a cycle or two is not.
+pub fn vip_cycles(opcode: u16, cpu: &CPU) -> u32 {
+    let vx = cpu.get_vreg_value(((opcode & 0x0F00) >> 8) as u8);
+    let vy = cpu.get_vreg_value(((opcode & 0x00F0) >> 4) as u8);
+    let kk = (opcode & 0x00FF) as u8;
+    let n = u32::from(opcode & 0x000F);
+
+    let cycles = match opcode & 0xF000 {
+        0x0000 => match opcode {
+            0x00E0 => 24,
+            0x00EE => 10,
+            // machine code subroutine, its length is unknown
+            _ => 12,
+        },
+        0x1000 => 12,
+        0x2000 => 26,
+        0x3000 => skip_cycles(vx == kk),
+        0x4000 => skip_cycles(vx != kk),
+        0x5000 => skip_cycles(vx == vy) + 4,
+        0x6000 => 6,
+        0x7000 => 10,
+        0x8000 => 44,
+        0x9000 => skip_cycles(vx != vy) + 4,
+        0xA000 => 12,
+        0xB000 => 22,
+        0xC000 => 36,
+        0xD000 => sprite_cycles(vx, n),
+        0xE000 => 14,
+        _ => match kk {
+            0x1E => 16,
+            0x29 => 16,
+            0x33 => bcd_cycles(vx),
+            0x55 | 0x65 => 14 + 14 * u32::from((opcode & 0x0F00) >> 8),
+            _ => 10,
+        },
+    };
+
+    VIP_FETCH_CYCLES + cycles
+}
+
+fn skip_cycles(taken: bool) -> u32 {
+    if taken {
+        14
+    } else {
+        10
+    }
+}
+
+// sprites not aligned to a byte are shifted bit by bit, row after row
+fn sprite_cycles(x: u8, rows: u32) -> u32 {
+    let shift = u32::from(x % 8);
+
+    if shift == 0 {
+        26 + rows * 34
+    } else {
+        26 + rows * (58 + 4 * shift)
+    }
+}
+
+// the VIP computes BCD digits by repeated subtraction
+fn bcd_cycles(value: u8) -> u32 {
+    let digits = u32::from(value / 100) + u32::from(value / 10 % 10) + u32::from(value % 10);
+    80 + 16 * digits
+}
@@ -0,0 +1,654 @@
+//! Execution trace, one line per instruction, written before the
+//! instruction runs:
+//!
+//! ```text
+//! frame=000001 pc=0200 op=6005 v0=00 v1=00 ... vf=00 i=0000 dt=00 st=00 sp=00 ; LD V0, 0x05
+//! ```
+//!
+//! Fields are separated by single spaces and always come in this order:
+//!
+//! - `frame`: frame the instruction runs in, counted from 1, decimal
+//! - `pc`: address of the instruction, 4 hex digits
+//! - `op`: the opcode, 4 hex digits
+//! - `v0` to `vf`: the V registers, 2 hex digits each
+//! - `i`: the I register, 4 hex digits
+//! - `dt`, `st`: delay and sound timers, 2 hex digits each
+//! - `sp`: stack depth, 2 hex digits
+//! - `;` and the mnemonic from `disasm::mnemonic`
+//!
+//! Hex digits are upper case. Everything after `;` is a comment for
+//! readers, tools compare the fields before it, so a trace from another
+//! emulator only needs to print the same `key=value` fields. `diff` and
+//! `chip8-headless trace-diff` compare `pc`, `v0` to `vf` and `i`, and
+//! only need those.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    ops::RangeInclusive,
+    path::Path,
+};
+
+use crate::cpu::CPU;
+use crate::disasm;
+use crate::error::Chip8Error;
+
+/// Which instructions go into a trace, all of them by default
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TraceFilter {
+    /// Addresses of the instructions to trace
+    pub pc: Option<RangeInclusive<u16>>,
+    /// Opcode classes to trace, bit n set for opcodes whose first digit is n
+    pub classes: Option<u16>,
+    /// Frames to trace, counted from 1
+    pub frames: Option<RangeInclusive<u64>>,
+}
+
+impl TraceFilter {
+    pub fn init() -> TraceFilter {
+        TraceFilter::default()
+    }
+
+    pub fn matches(&self, frame: u64, pc: u16, opcode: u16) -> bool {
+        self.pc.as_ref().is_none_or(|range| range.contains(&pc))
+            && self
+                .classes
+                .is_none_or(|classes| classes & (1 << (opcode >> 12)) != 0)
+            && self
+                .frames
+                .as_ref()
+                .is_none_or(|range| range.contains(&frame))
+    }
+
+    /// Past the last frame of the window nothing more is traced
+    pub fn is_done(&self, frame: u64) -> bool {
+        self.frames
+            .as_ref()
+            .is_some_and(|range| frame > *range.end())
+    }
+}
+
+/// `--trace <file>` and its filters, as both binaries take them
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TraceOptions {
+    /// Every instruction goes here
+    pub path: Option<String>,
+    pub filter: TraceFilter,
+}
+
+impl TraceOptions {
+    pub fn init() -> TraceOptions {
+        TraceOptions::default()
+    }
+
+    /// Parses `arg`, taking its value from `args`, if it is one of
+    /// `--trace --trace-pc --trace-op --trace-frames`. Returns false for
+    /// any other argument.
+    pub fn parse_arg(
+        &mut self,
+        arg: &str,
+        args: &mut impl Iterator<Item = String>,
+    ) -> Result<bool, String> {
+        match arg {
+            "--trace" => {
+                self.path = Some(args.next().ok_or("--trace needs a file name")?);
+            }
+            "--trace-pc" => {
+                let value = args.next().ok_or("--trace-pc needs a range")?;
+                self.filter.pc = Some(parse_pc_range(&value)?);
+            }
+            "--trace-op" => {
+                let value = args.next().ok_or("--trace-op needs opcode classes")?;
+                self.filter.classes = Some(parse_classes(&value)?);
+            }
+            "--trace-frames" => {
+                let value = args.next().ok_or("--trace-frames needs a range")?;
+                self.filter.frames = Some(parse_frame_range(&value)?);
+            }
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    /// Called once every argument is parsed, filters need `--trace`
+    pub fn check(&self) -> Result<(), String> {
+        if self.path.is_none() && self.filter != TraceFilter::init() {
+            return Err("the trace filters need --trace".to_string());
+        }
+        Ok(())
+    }
+
+    /// The tracer to give `Chip8::set_tracer`, if `--trace` was given
+    pub fn create_tracer(&self) -> Result<Option<Tracer>, Chip8Error> {
+        self.path
+            .as_ref()
+            .map(|path| Tracer::create(path, self.filter.clone()))
+            .transpose()
+    }
+}
+
+/// `200-2FF` or a single `200`, in hex
+pub fn parse_pc_range(text: &str) -> Result<RangeInclusive<u16>, String> {
+    parse_range(text, |part| {
+        let digits = part.strip_prefix("0x").unwrap_or(part);
+        u16::from_str_radix(digits, 16).ok()
+    })
+    .ok_or_else(|| format!("invalid address range {}", text))
+}
+
+/// `100-200` or a single `100`, counted from 1
+pub fn parse_frame_range(text: &str) -> Result<RangeInclusive<u64>, String> {
+    parse_range(text, |part| part.parse().ok().filter(|&frame| frame > 0))
+        .ok_or_else(|| format!("invalid frame range {}", text))
+}
+
+/// First digits of the opcodes to trace, e.g. `8,D,F`
+pub fn parse_classes(text: &str) -> Result<u16, String> {
+    text.split(',').try_fold(0, |classes, class| {
+        match u8::from_str_radix(class.trim(), 16) {
+            Ok(digit) if digit < 16 => Ok(classes | 1 << digit),
+            _ => Err(format!("invalid opcode class {}, expected 0 to F", class)),
+        }
+    })
+}
+
+fn parse_range<T: PartialOrd>(
+    text: &str,
+    parse: impl Fn(&str) -> Option<T>,
+) -> Option<RangeInclusive<T>> {
+    let (start, end) = match text.split_once('-') {
+        Some((start, end)) => (parse(start)?, parse(end)?),
+        None => (parse(text)?, parse(text)?),
+    };
+
+    (start <= end).then_some(start..=end)
+}
+
+/// The trace line of the instruction `opcode` at `pc` in `frame`, as
+/// described at the top of this module, without the line break
+pub fn trace_line(frame: u64, pc: u16, opcode: u16, cpu: &CPU) -> String {
+    let mut line = format!("frame={:06} pc={:04X} op={:04X}", frame, pc, opcode);
+
+    for x in 0..16 {
+        line.push_str(&format!(" v{:x}={:02X}", x, cpu.get_vreg_value(x)));
+    }
+
+    line.push_str(&format!(
+        " i={:04X} dt={:02X} st={:02X} sp={:02X} ; {}",
+        cpu.get_i_reg_value(),
+        cpu.get_delay_timer(),
+        cpu.get_sound_timer(),
+        cpu.get_sp(),
+        disasm::mnemonic(opcode)
+    ));
+    line
+}
+
+/// Writes the trace of a running `Chip8`, see `Chip8::set_tracer`. Frames
+/// are counted from when tracing started.
+pub struct Tracer {
+    out: Box<dyn Write>,
+    filter: TraceFilter,
+    frame: u64,
+    lines: u64,
+    // the first write that failed, tracing stops there
+    error: Option<io::Error>,
+}
+
+impl Tracer {
+    pub fn init(out: Box<dyn Write>, filter: TraceFilter) -> Tracer {
+        Tracer {
+            out,
+            filter,
+            frame: 1,
+            lines: 0,
+            error: None,
+        }
+    }
+
+    /// Traces to a new file at `path`
+    pub fn create<P: AsRef<Path>>(path: P, filter: TraceFilter) -> Result<Tracer, Chip8Error> {
+        let file = File::create(path).map_err(Chip8Error::Output)?;
+        Ok(Tracer::init(Box::new(BufWriter::new(file)), filter))
+    }
+
+    /// Lines written so far
+    pub fn lines(&self) -> u64 {
+        self.lines
+    }
+
+    /// Called right before `opcode` at `pc` runs
+    pub fn trace(&mut self, cpu: &CPU, pc: u16, opcode: u16) {
+        if self.error.is_some() || self.is_done() || !self.filter.matches(self.frame, pc, opcode) {
+            return;
+        }
+
+        match writeln!(self.out, "{}", trace_line(self.frame, pc, opcode, cpu)) {
+            Ok(()) => self.lines += 1,
+            Err(err) => self.error = Some(err),
+        }
+    }
+
+    /// Flushes the output once the frame window has passed, so the trace
+    /// is complete on disk while the emulator keeps running
+    pub fn end_frame(&mut self) {
+        let was_done = self.is_done();
+        self.frame += 1;
+
+        if self.is_done() && !was_done && self.error.is_none() {
+            if let Err(err) = self.out.flush() {
+                self.error = Some(err);
+            }
+        }
+    }
+
+    /// True once the frame window has passed, nothing more is traced
+    pub fn is_done(&self) -> bool {
+        self.filter.is_done(self.frame)
+    }
+
+    /// Flushes the output and reports the first write that failed
+    pub fn finish(mut self) -> Result<(), Chip8Error> {
+        match self.error.take() {
+            Some(err) => Err(Chip8Error::Output(err)),
+            None => self.out.flush().map_err(Chip8Error::Output),
+        }
+    }
+}
+
+/// The fields of a trace line that `diff` compares
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEntry {
+    /// Line number in the file, counted from 1
+    pub line: usize,
+    pub text: String,
+    pub pc: u16,
+    pub v: [u8; 16],
+    pub i: u16,
+}
+
+impl TraceEntry {
+    /// Reads the `key=value` fields before any `;`, in any order. Other
+    /// fields are ignored, `pc`, `v0` to `vf` and `i` have to be there.
+    pub fn parse(line: usize, text: &str) -> Option<TraceEntry> {
+        let fields = text.split(';').next().unwrap_or_default();
+        let mut pc = None;
+        let mut v = [None; 16];
+        let mut i = None;
+
+        for field in fields.split_whitespace() {
+            let Some((key, value)) = field.split_once('=') else {
+                continue;
+            };
+            let key = key.to_ascii_lowercase();
+
+            match key.as_str() {
+                "pc" => pc = Some(u16::from_str_radix(value, 16).ok()?),
+                "i" => i = Some(u16::from_str_radix(value, 16).ok()?),
+                _ => {
+                    if let Some(x) = key.strip_prefix('v') {
+                        if let Ok(x @ 0..=15) = usize::from_str_radix(x, 16) {
+                            v[x] = Some(u8::from_str_radix(value, 16).ok()?);
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut registers = [0; 16];
+        for (register, value) in registers.iter_mut().zip(v) {
+            *register = value?;
+        }
+
+        Some(TraceEntry {
+            line,
+            text: text.to_string(),
+            pc: pc?,
+            v: registers,
+            i: i?,
+        })
+    }
+
+    /// Names of the fields that differ, empty if none does
+    pub fn differences(&self, other: &TraceEntry) -> Vec<String> {
+        let mut fields = Vec::new();
+
+        if self.pc != other.pc {
+            fields.push("pc".to_string());
+        }
+        for x in 0..16 {
+            if self.v[x] != other.v[x] {
+                fields.push(format!("v{:x}", x));
+            }
+        }
+        if self.i != other.i {
+            fields.push("i".to_string());
+        }
+        fields
+    }
+}
+
+/// Every instruction of a trace, blank lines are skipped
+pub fn parse_trace(text: &str) -> Result<Vec<TraceEntry>, Chip8Error> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            TraceEntry::parse(index + 1, line).ok_or(Chip8Error::InvalidTrace { line: index + 1 })
+        })
+        .collect()
+}
+
+pub fn read_trace<P: AsRef<Path>>(path: P) -> Result<Vec<TraceEntry>, Chip8Error> {
+    let text = fs::read_to_string(path).map_err(Chip8Error::TraceFile)?;
+    parse_trace(&text)
+}
+
+/// Where two traces stop agreeing, see `diff`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// Index of the first instruction that differs, counted from 0
+    pub index: usize,
+    /// Fields that differ there, empty when one trace ends before the other
+    pub fields: Vec<String>,
+}
+
+impl Divergence {
+    /// Says where the traces differ, showing up to `context` instructions
+    /// before the divergence once and up to `context` after it from each
+    /// trace, unified diff style:
+    ///
+    /// ```text
+    /// first difference at instruction 3 in v0, i
+    /// @@ a.trace line 1, b.trace line 1 @@
+    ///   frame=000001 pc=0200 ...
+    /// - frame=000001 pc=0204 ...
+    /// + frame=000001 pc=0204 ...
+    /// ```
+    pub fn report(
+        &self,
+        a_name: &str,
+        a: &[TraceEntry],
+        b_name: &str,
+        b: &[TraceEntry],
+        context: usize,
+    ) -> String {
+        let mut out = if self.fields.is_empty() {
+            let (shorter, longer) = if a.len() < b.len() {
+                (a_name, b_name)
+            } else {
+                (b_name, a_name)
+            };
+            format!(
+                "{} ends after {} instructions, {} goes on\n",
+                shorter, self.index, longer
+            )
+        } else {
+            format!(
+                "first difference at instruction {} in {}\n",
+                self.index + 1,
+                self.fields.join(", ")
+            )
+        };
+
+        let start = self.index.saturating_sub(context);
+        let end = self.index + context + 1;
+        let first_line = |trace: &[TraceEntry]| {
+            trace
+                .get(start)
+                .map_or_else(|| "end".to_string(), |entry| format!("line {}", entry.line))
+        };
+        out.push_str(&format!(
+            "@@ {} {}, {} {} @@\n",
+            a_name,
+            first_line(a),
+            b_name,
+            first_line(b)
+        ));
+
+        for entry in &a[start..self.index] {
+            out.push_str(&format!("  {}\n", entry.text));
+        }
+        for entry in a.iter().take(end).skip(self.index) {
+            out.push_str(&format!("- {}\n", entry.text));
+        }
+        for entry in b.iter().take(end).skip(self.index) {
+            out.push_str(&format!("+ {}\n", entry.text));
+        }
+        out
+    }
+}
+
+/// First instruction where `pc`, a V register or `i` differ, or where one
+/// trace ends before the other. `None` when the traces agree.
+pub fn diff(a: &[TraceEntry], b: &[TraceEntry]) -> Option<Divergence> {
+    let divergence = a.iter().zip(b).enumerate().find_map(|(index, (a, b))| {
+        let fields = a.differences(b);
+        (!fields.is_empty()).then_some(Divergence { index, fields })
+    });
+
+    divergence.or_else(|| {
+        (a.len() != b.len()).then(|| Divergence {
+            index: a.len().min(b.len()),
+            fields: Vec::new(),
+        })
+    })
+}
+
+#[cfg(test)]
+mod trace_tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::chip8::Chip8;
+
+    // output the test can still read once the tracer owns it
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(data)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuffer {
+        fn lines(&self) -> Vec<String> {
+            String::from_utf8(self.0.borrow().clone())
+                .unwrap()
+                .lines()
+                .map(str::to_string)
+                .collect()
+        }
+    }
+
+    // V0 += 1, V1 = V0, loop
+    fn traced(filter: TraceFilter) -> (Chip8, SharedBuffer) {
+        let buffer = SharedBuffer::default();
+        let mut emulator = Chip8::init();
+        emulator
+            .load_rom(vec![0x70, 0x01, 0x81, 0x00, 0x12, 0x00])
+            .unwrap();
+        emulator.set_tracer(Tracer::init(Box::new(buffer.clone()), filter));
+        (emulator, buffer)
+    }
+
+    #[test]
+    fn test_line_format() {
+        let mut cpu = CPU::init();
+        cpu.set_vreg_value(0xA, 0x5C);
+        cpu.set_i_reg_value(0x2F0);
+        cpu.set_sound_timer(3);
+
+        assert_eq!(
+            trace_line(12, 0x204, 0xDAB5, &cpu),
+            "frame=000012 pc=0204 op=DAB5 \
+             v0=00 v1=00 v2=00 v3=00 v4=00 v5=00 v6=00 v7=00 \
+             v8=00 v9=00 va=5C vb=00 vc=00 vd=00 ve=00 vf=00 \
+             i=02F0 dt=00 st=03 sp=00 ; DRW VA, VB, 5"
+        );
+    }
+
+    #[test]
+    fn test_state_before_instruction() {
+        let (mut emulator, buffer) = traced(TraceFilter::init());
+        emulator.run_frame().unwrap();
+
+        let lines = buffer.lines();
+        // 8 instructions per frame at the default 500 per second
+        assert_eq!(lines.len(), 8);
+        assert!(lines[0].starts_with("frame=000001 pc=0200 op=7001 v0=00 "));
+        assert!(lines[3].starts_with("frame=000001 pc=0200 op=7001 v0=01 v1=01 "));
+        assert_eq!(emulator.take_tracer().unwrap().lines(), 8);
+    }
+
+    #[test]
+    fn test_filters() {
+        let filter = TraceFilter {
+            pc: Some(parse_pc_range("202-204").unwrap()),
+            classes: Some(parse_classes("8").unwrap()),
+            frames: Some(parse_frame_range("2-3").unwrap()),
+        };
+        let (mut emulator, buffer) = traced(filter);
+
+        for _ in 0..2 {
+            emulator.run_frame().unwrap();
+        }
+        let lines = buffer.lines();
+        assert!(!emulator.take_tracer().unwrap().is_done());
+        assert!(lines.len() > 1);
+        assert!(lines
+            .iter()
+            .all(|line| line.starts_with("frame=000002 pc=0202 op=8100")));
+    }
+
+    #[test]
+    fn test_done_after_frame_window() {
+        let filter = TraceFilter {
+            frames: Some(parse_frame_range("2").unwrap()),
+            ..TraceFilter::init()
+        };
+        let (mut emulator, buffer) = traced(filter);
+
+        for _ in 0..3 {
+            emulator.run_frame().unwrap();
+        }
+        let tracer = emulator.take_tracer().unwrap();
+        assert!(tracer.is_done());
+        assert_eq!(tracer.lines(), 8);
+        assert_eq!(buffer.lines().len(), 8);
+    }
+
+    #[test]
+    fn test_options() {
+        let mut options = TraceOptions::init();
+        let mut args = ["0-1F", "x"].map(String::from).into_iter();
+
+        assert_eq!(options.parse_arg("--trace-pc", &mut args), Ok(true));
+        assert_eq!(
+            options.check(),
+            Err("the trace filters need --trace".to_string())
+        );
+        assert!(options.parse_arg("--trace-frames", &mut args).is_err());
+        assert_eq!(options.parse_arg("--frames", &mut args), Ok(false));
+
+        options.path = Some("out.trace".to_string());
+        assert_eq!(options.check(), Ok(()));
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse_pc_range("0x200-2ff"), Ok(0x200..=0x2FF));
+        assert_eq!(parse_pc_range("300"), Ok(0x300..=0x300));
+        assert!(parse_pc_range("300-200").is_err());
+        assert_eq!(parse_frame_range("10-20"), Ok(10..=20));
+        assert!(parse_frame_range("0").is_err());
+        assert_eq!(parse_classes("0,D,f"), Ok(0b1010_0000_0000_0001));
+        assert!(parse_classes("G").is_err());
+    }
+
+    fn trace_of(lines: &[&str]) -> Vec<TraceEntry> {
+        parse_trace(&lines.join("\n")).unwrap()
+    }
+
+    #[test]
+    fn test_parse_entry() {
+        let cpu = CPU::init();
+        let line = trace_line(1, 0x2FE, 0x00E0, &cpu);
+        let entry = TraceEntry::parse(7, &line).unwrap();
+        assert_eq!((entry.line, entry.pc, entry.i), (7, 0x2FE, 0));
+
+        // another emulator's order and case, with extra fields
+        let other = "PC=2FE I=0000 V0=00 V1=00 V2=00 V3=00 V4=00 V5=00 V6=00 V7=00 \
+                     V8=00 V9=00 VA=00 VB=00 VC=00 VD=00 VE=00 VF=00 cycles=1234";
+        assert!(TraceEntry::parse(1, other)
+            .unwrap()
+            .differences(&entry)
+            .is_empty());
+
+        assert_eq!(TraceEntry::parse(1, "pc=200 i=0000 ; no registers"), None);
+        assert!(matches!(
+            parse_trace("\n\npc=200"),
+            Err(Chip8Error::InvalidTrace { line: 3 })
+        ));
+    }
+
+    #[test]
+    fn test_diff() {
+        let mut cpu = CPU::init();
+        let mut a = Vec::new();
+        for pc in (0x200..0x20A).step_by(2) {
+            a.push(trace_line(1, pc, 0x7001, &cpu));
+        }
+        cpu.set_vreg_value(3, 1);
+        let mut b = a.clone();
+        b[3] = trace_line(1, 0x206, 0x7001, &cpu);
+        b[4] = trace_line(1, 0x20A, 0x7001, &cpu);
+
+        let a: Vec<&str> = a.iter().map(String::as_str).collect();
+        let b: Vec<&str> = b.iter().map(String::as_str).collect();
+        let (a, b) = (trace_of(&a), trace_of(&b));
+
+        let divergence = diff(&a, &b).unwrap();
+        assert_eq!(
+            divergence,
+            Divergence {
+                index: 3,
+                fields: vec!["v3".to_string()]
+            }
+        );
+
+        let report = divergence.report("a", &a, "b", &b, 1);
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines[0], "first difference at instruction 4 in v3");
+        assert_eq!(lines[1], "@@ a line 3, b line 3 @@");
+        assert!(lines[2].starts_with("  frame=000001 pc=0204 "));
+        assert!(lines[3].starts_with("- frame=000001 pc=0206 "));
+        assert!(lines[4].starts_with("- frame=000001 pc=0208 "));
+        assert!(lines[5].starts_with("+ frame=000001 pc=0206 "));
+        assert!(lines[6].starts_with("+ frame=000001 pc=020A "));
+        assert_eq!(lines.len(), 7);
+
+        assert_eq!(diff(&a, &a), None);
+    }
+
+    #[test]
+    fn test_diff_length() {
+        let cpu = CPU::init();
+        let line = trace_line(1, 0x200, 0x1200, &cpu);
+        let a = trace_of(&[&line, &line]);
+        let b = trace_of(&[&line]);
+
+        let divergence = diff(&a, &b).unwrap();
+        assert_eq!(divergence.index, 1);
+        assert!(divergence.fields.is_empty());
+        assert!(divergence
+            .report("a", &a, "b", &b, 3)
+            .starts_with("b ends after 1 instructions, a goes on\n@@ a line 1, b line 1 @@\n  "));
+    }
+}
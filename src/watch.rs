@@ -0,0 +1,146 @@
+use std::{fmt, str::FromStr};
+
+use crate::named;
+
+/// Which memory accesses a watchpoint stops on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    /// Reads and writes
+    Access,
+    /// Writes that store a different value
+    Change,
+}
+
+impl WatchKind {
+    pub const ALL: [WatchKind; 4] = [
+        WatchKind::Read,
+        WatchKind::Write,
+        WatchKind::Access,
+        WatchKind::Change,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            WatchKind::Read => "read",
+            WatchKind::Write => "write",
+            WatchKind::Access => "access",
+            WatchKind::Change => "change",
+        }
+    }
+}
+
+impl fmt::Display for WatchKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Full name or its first letter
+impl FromStr for WatchKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let first_letter = WatchKind::ALL
+            .into_iter()
+            .find(|kind| kind.name()[..1].eq_ignore_ascii_case(s));
+
+        match first_letter {
+            Some(kind) => Ok(kind),
+            None => named::parse_named(&WatchKind::ALL, WatchKind::name, "watchpoint kind", s),
+        }
+    }
+}
+
+/// Watch on `len` bytes of memory from `addr`, see `Bus::add_watchpoint`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub addr: u16,
+    pub len: u16,
+    pub kind: WatchKind,
+}
+
+impl Watchpoint {
+    pub fn init(kind: WatchKind, addr: u16, len: u16) -> Watchpoint {
+        Watchpoint {
+            addr,
+            len: len.max(1),
+            kind,
+        }
+    }
+
+    pub fn contains(&self, addr: u16) -> bool {
+        addr.wrapping_sub(self.addr) < self.len
+    }
+
+    /// Whether reading `addr` stops here
+    pub fn on_read(&self, addr: u16) -> bool {
+        matches!(self.kind, WatchKind::Read | WatchKind::Access) && self.contains(addr)
+    }
+
+    /// Whether writing `value` over `old` at `addr` stops here
+    pub fn on_write(&self, addr: u16, old: u8, value: u8) -> bool {
+        let kind = match self.kind {
+            WatchKind::Write | WatchKind::Access => true,
+            WatchKind::Change => old != value,
+            WatchKind::Read => false,
+        };
+        kind && self.contains(addr)
+    }
+}
+
+/// First access that hit a watchpoint since `Bus::take_watch_hit`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    pub watchpoint: Watchpoint,
+    pub addr: u16,
+    /// True for a write, false for a read
+    pub write: bool,
+    /// Memory before the access
+    pub old: u8,
+    /// Memory after the access, the same as `old` for a read
+    pub value: u8,
+}
+
+/// `read 300 = 05` or `write 300 = 05 (was 00)`
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.write {
+            write!(
+                f,
+                "write {:03X} = {:02X} (was {:02X})",
+                self.addr, self.value, self.old
+            )
+        } else {
+            write!(f, "read {:03X} = {:02X}", self.addr, self.value)
+        }
+    }
+}
+
+#[cfg(test)]
+mod watch_tests {
+    use super::*;
+
+    #[test]
+    fn test_kinds() {
+        let write = Watchpoint::init(WatchKind::Write, 0x300, 3);
+        let change = Watchpoint::init(WatchKind::Change, 0x300, 3);
+        let access = Watchpoint::init(WatchKind::Access, 0x300, 3);
+
+        assert!(write.on_write(0x302, 1, 1));
+        assert!(!write.on_write(0x303, 1, 2));
+        assert!(!write.on_read(0x300));
+        assert!(!change.on_write(0x300, 1, 1));
+        assert!(change.on_write(0x300, 1, 2));
+        assert!(access.on_read(0x301) && access.on_write(0x301, 0, 0));
+        assert!(!access.on_read(0x2FF));
+    }
+
+    #[test]
+    fn test_kind_names() {
+        assert_eq!("w".parse(), Ok(WatchKind::Write));
+        assert_eq!("change".parse(), Ok(WatchKind::Change));
+        assert!("x".parse::<WatchKind>().is_err());
+    }
+}
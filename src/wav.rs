@@ -0,0 +1,150 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use crate::{
+    audio::{self, Audio, SampleGenerator},
+    chip8::Chip8,
+    error::Chip8Error,
+    frontend::AudioSink,
+};
+
+const BITS_PER_SAMPLE: u16 = 16;
+const CHANNELS: u16 = 1;
+
+/// Audio driver that keeps every generated sample and writes them out as a
+/// 16 bit mono PCM WAV file
+pub struct WavWriter {
+    generator: SampleGenerator,
+    samples: Vec<i16>,
+}
+
+impl WavWriter {
+    pub fn init() -> WavWriter {
+        WavWriter::with_generator(SampleGenerator::init(audio::SAMPLE_RATE))
+    }
+
+    pub fn with_generator(generator: SampleGenerator) -> WavWriter {
+        WavWriter {
+            generator,
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn samples(&self) -> &[i16] {
+        &self.samples
+    }
+
+    pub fn write_to<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let sample_rate = self.generator.sample_rate();
+        let block_align = CHANNELS * BITS_PER_SAMPLE / 8;
+        let byte_rate = sample_rate * u32::from(block_align);
+        let data_size = (self.samples.len() * usize::from(block_align)) as u32;
+
+        out.write_all(b"RIFF")?;
+        out.write_all(&(36 + data_size).to_le_bytes())?;
+        out.write_all(b"WAVE")?;
+
+        out.write_all(b"fmt ")?;
+        out.write_all(&16u32.to_le_bytes())?;
+        // 1 = uncompressed PCM
+        out.write_all(&1u16.to_le_bytes())?;
+        out.write_all(&CHANNELS.to_le_bytes())?;
+        out.write_all(&sample_rate.to_le_bytes())?;
+        out.write_all(&byte_rate.to_le_bytes())?;
+        out.write_all(&block_align.to_le_bytes())?;
+        out.write_all(&BITS_PER_SAMPLE.to_le_bytes())?;
+
+        out.write_all(b"data")?;
+        out.write_all(&data_size.to_le_bytes())?;
+        for sample in &self.samples {
+            out.write_all(&sample.to_le_bytes())?;
+        }
+
+        out.flush()
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let file = File::create(path)?;
+        self.write_to(BufWriter::new(file))
+    }
+}
+
+impl AudioSink for WavWriter {
+    fn play_frame(&mut self, audio: &Audio, playing: bool) {
+        let frame = self.generator.generate_frame(audio, playing);
+
+        self.samples.extend(
+            frame
+                .iter()
+                .map(|&sample| (sample * f32::from(i16::MAX)) as i16),
+        );
+    }
+}
+
+/// Runs `frames` frames of the loaded ROM without a window and returns
+/// the sound they made
+pub fn record_audio(
+    emulator: &mut Chip8,
+    frames: usize,
+    mut writer: WavWriter,
+) -> Result<WavWriter, Chip8Error> {
+    for _ in 0..frames {
+        emulator.run_frame()?;
+        writer.play_frame(emulator.bus.get_audio(), emulator.is_sound_playing());
+    }
+
+    Ok(writer)
+}
+
+/// Runs `frames` frames of the loaded ROM without a window and writes the
+/// sound they made to a WAV file at `path`
+pub fn export_wav<P: AsRef<Path>>(
+    emulator: &mut Chip8,
+    frames: usize,
+    path: P,
+) -> Result<(), Chip8Error> {
+    let writer = record_audio(emulator, frames, WavWriter::init())?;
+    writer.save(path).map_err(Chip8Error::Output)
+}
+
+#[cfg(test)]
+mod wav_tests {
+    use super::*;
+
+    #[test]
+    fn test_header() {
+        let mut writer = WavWriter::init();
+        writer.play_frame(&Audio::init(), false);
+
+        let mut bytes = Vec::new();
+        writer.write_to(&mut bytes).unwrap();
+
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(&bytes[8..16], b"WAVEfmt ");
+        assert_eq!(&bytes[24..28], &44100u32.to_le_bytes());
+        assert_eq!(&bytes[36..40], b"data");
+        assert_eq!(&bytes[40..44], &(735u32 * 2).to_le_bytes());
+        assert_eq!(bytes.len(), 44 + 735 * 2);
+    }
+
+    #[test]
+    fn test_record_sound_timer() {
+        let mut emulator = Chip8::init();
+        // V0 = 2, sound timer = V0, loop forever
+        emulator
+            .load_rom(vec![0x60, 0x02, 0xF0, 0x18, 0x12, 0x04])
+            .unwrap();
+
+        let writer = record_audio(&mut emulator, 4, WavWriter::init()).unwrap();
+        let frames: Vec<&[i16]> = writer.samples().chunks(735).collect();
+
+        // ST = 2 sounds for two whole frames
+        assert_eq!(frames.len(), 4);
+        assert!(frames[0].iter().any(|&sample| sample != 0));
+        assert!(frames[1].iter().any(|&sample| sample != 0));
+        assert!(frames[2].iter().all(|&sample| sample == 0));
+    }
+}
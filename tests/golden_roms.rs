@@ -0,0 +1,163 @@
+//! Runs the Timendus test suite ROMs in `data/tim` headless and compares the
+//! screen with the snapshots in `tests/golden`. Run with `UPDATE_GOLDEN=1`
+//! to rewrite the snapshots after a deliberate change, then check them by eye.
+
+use std::{env, fs, path::PathBuf};
+
+use chip_8::{Chip8, Platform, PlatformConfig};
+
+// frames before each key press, long enough for the menus to show up,
+// and how long every key is held
+const FRAMES_BEFORE_KEY: usize = 120;
+const FRAMES_KEY_HELD: usize = 20;
+
+// loads a ROM with a fixed seed and picks menu entries by tapping `keys`
+fn boot(rom: &str, platform: Option<Platform>, keys: &[usize]) -> Chip8 {
+    let mut config = match platform {
+        Some(platform) => platform.config(),
+        None => PlatformConfig::default(),
+    };
+    config.seed = Some(0);
+
+    let mut emulator = Chip8::with_config(config);
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    emulator
+        .load_rom_file(root.join("data/tim").join(rom))
+        .unwrap();
+
+    for &key in keys {
+        run_frames(&mut emulator, FRAMES_BEFORE_KEY);
+
+        emulator.bus.handle_key_press(key, true);
+        run_frames(&mut emulator, FRAMES_KEY_HELD);
+        emulator.bus.handle_key_press(key, false);
+    }
+
+    emulator
+}
+
+fn run_frames(emulator: &mut Chip8, frames: usize) {
+    for _ in 0..frames {
+        emulator.run_frame().unwrap();
+    }
+}
+
+fn run_rom(rom: &str, platform: Option<Platform>, keys: &[usize], frames: usize) -> Chip8 {
+    let mut emulator = boot(rom, platform, keys);
+    run_frames(&mut emulator, frames);
+    emulator
+}
+
+fn check_golden(name: &str, emulator: &Chip8) {
+    let screen = emulator.bus.display_to_text();
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{}.txt", name));
+
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&path, &screen).unwrap();
+        return;
+    }
+
+    let golden = fs::read_to_string(&path)
+        .unwrap_or_else(|err| panic!("missing golden {}: {}", path.display(), err));
+
+    assert!(
+        screen == golden,
+        "{} differs from {}\n{}",
+        name,
+        path.display(),
+        screen
+    );
+}
+
+#[test]
+fn chip8_logo() {
+    check_golden("1-chip8-logo", &run_rom("1-chip8-logo.ch8", None, &[], 60));
+}
+
+#[test]
+fn ibm_logo() {
+    check_golden("2-ibm-logo", &run_rom("2-ibm-logo.ch8", None, &[], 60));
+}
+
+#[test]
+fn corax() {
+    check_golden("3-corax+", &run_rom("3-corax+.ch8", None, &[], 120));
+}
+
+#[test]
+fn flags() {
+    check_golden("4-flags", &run_rom("4-flags.ch8", None, &[], 120));
+}
+
+// the menu asks which platform to test, 1 is CHIP-8
+#[test]
+fn quirks_vip() {
+    let emulator = run_rom("5-quirks.ch8", Some(Platform::CosmacVip), &[1], 600);
+    check_golden("5-quirks-vip", &emulator);
+}
+
+// 2 is SUPER-CHIP, then 1 for the modern variant
+#[test]
+fn quirks_schip() {
+    let emulator = run_rom("5-quirks.ch8", Some(Platform::SuperChip11), &[2, 1], 600);
+    check_golden("5-quirks-schip", &emulator);
+}
+
+// CHIP-48 has no SUPER-CHIP opcodes, so it takes the CHIP-8 test as well
+// and the snapshot shows where its quirks part from the VIP
+#[test]
+fn quirks_chip48() {
+    let emulator = run_rom("5-quirks.ch8", Some(Platform::Chip48), &[1], 600);
+    check_golden("5-quirks-chip48", &emulator);
+}
+
+// 3 is XO-CHIP
+#[test]
+fn quirks_xochip() {
+    let emulator = run_rom("5-quirks.ch8", Some(Platform::XoChip), &[3], 600);
+    check_golden("5-quirks-xochip", &emulator);
+}
+
+// 1 picks the Ex9E test, which highlights the keys held down
+#[test]
+fn keypad_ex9e() {
+    let mut emulator = boot("6-keypad.ch8", None, &[1]);
+    run_frames(&mut emulator, 30);
+
+    emulator.bus.handle_key_press(0x5, true);
+    emulator.bus.handle_key_press(0xA, true);
+    run_frames(&mut emulator, 30);
+    check_golden("6-keypad-ex9e", &emulator);
+}
+
+// holding B plays the buzzer and shows the speaker
+#[test]
+fn beep() {
+    let mut emulator = boot("7-beep.ch8", None, &[]);
+    emulator.bus.handle_key_press(0xB, true);
+    run_frames(&mut emulator, 30);
+
+    assert!(emulator.cpu.get_sound_timer() > 0);
+    check_golden("7-beep", &emulator);
+}
+
+// 1 picks SUPER-CHIP, 1 low resolution, then 1 for the modern full pixel scrolling
+#[test]
+fn scrolling_schip_lores() {
+    let emulator = run_rom(
+        "8-scrolling.ch8",
+        Some(Platform::SuperChip11),
+        &[1, 1, 1],
+        300,
+    );
+    check_golden("8-scrolling-schip-lores", &emulator);
+}
+
+// 2 picks XO-CHIP, then 1 for high resolution
+#[test]
+fn scrolling_xochip_hires() {
+    let emulator = run_rom("8-scrolling.ch8", Some(Platform::XoChip), &[2, 1], 300);
+    check_golden("8-scrolling-xochip-hires", &emulator);
+}